@@ -0,0 +1,470 @@
+use escrow::{
+    instruction_builders::{
+        self, ApproveMilestoneAccounts, BasketLegAccounts, SettleAccounts, TakeAccounts,
+        UpdateAccounts,
+    },
+    instructions::{EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{Auction, Config, Escrow},
+};
+use solana_instruction::Instruction;
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use solana_signature::Signature;
+use solana_signer::Signer;
+use solana_system_interface::program as system_program;
+use spl_associated_token_account_interface::address::get_associated_token_address_with_program_id;
+use spl_token_interface::native_mint;
+
+use crate::{error::CliError, rpc::EscrowRpc};
+
+/// Builds and sends escrow instructions for a single signing keypair.
+pub struct EscrowClient<R> {
+    rpc: R,
+    program_id: Pubkey,
+    payer: Keypair,
+}
+
+impl<R: EscrowRpc> EscrowClient<R> {
+    pub fn new(rpc: R, program_id: Pubkey, payer: Keypair) -> Self {
+        Self {
+            rpc,
+            program_id,
+            payer,
+        }
+    }
+
+    pub fn rpc(&self) -> &R {
+        &self.rpc
+    }
+
+    /// Offers `args.amount_offered` of `mint_a` from the payer's associated
+    /// token account (or wallet, for native SOL), bundled with each
+    /// `(mint, amount)` in `basket`. Returns the escrow address.
+    pub fn make(
+        &self,
+        mint_a: &Pubkey,
+        mint_b: &Pubkey,
+        mut args: MakeArgs,
+        basket: &[(Pubkey, u64)],
+    ) -> Result<(Pubkey, Signature), CliError> {
+        let maker = self.payer.pubkey();
+        let token_program = self.asset_program(mint_a)?;
+        let maker_token_a = asset_account(&maker, mint_a, &token_program);
+        let (escrow, _) = instruction_builders::find_escrow_address(
+            &self.program_id,
+            &maker,
+            mint_a,
+            mint_b,
+            args.seed,
+        );
+
+        let legs = basket
+            .iter()
+            .map(|(mint, _)| self.basket_leg(&maker, mint))
+            .collect::<Result<Vec<_>, _>>()?;
+        args.basket = basket.iter().map(|(_, amount)| *amount).collect();
+        let ix = instruction_builders::with_basket(
+            &self.program_id,
+            instruction_builders::make(
+                &self.program_id,
+                &maker,
+                mint_a,
+                mint_b,
+                &maker_token_a,
+                &token_program,
+                args,
+            ),
+            &escrow,
+            &legs,
+        );
+        let signature = self.rpc.send(&[ix], &self.payer)?;
+        Ok((escrow, signature))
+    }
+
+    /// Pays `args.amount_offered` of `mint_a` into an escrow held for
+    /// `seller`, which only `arbiter`, or a concession by either side, can
+    /// settle. The price in `args` is ignored; there is no mint B.
+    pub fn make_arbitrated(
+        &self,
+        mint_a: &Pubkey,
+        args: MakeArgs,
+        arbiter: &Pubkey,
+        seller: &Pubkey,
+    ) -> Result<(Pubkey, Signature), CliError> {
+        let kind = EscrowKind::Arbitrated {
+            arbiter: *arbiter,
+            seller: *seller,
+        };
+        self.make_payment(mint_a, args, kind)
+    }
+
+    /// Pays `amount` of mint B (everything still owed when `None`) and
+    /// receives the matching share of mint A. During an auction `amount` is
+    /// the most the payer will pay for the rest of the deposit.
+    pub fn take(
+        &self,
+        escrow_address: &Pubkey,
+        amount: Option<u64>,
+    ) -> Result<Signature, CliError> {
+        self.take_paying_with(escrow_address, None, amount)
+    }
+
+    /// Like [`Self::take`], but pays in `payment_mint` when it is one of the
+    /// escrow's other payment mints.
+    pub fn take_paying_with(
+        &self,
+        escrow_address: &Pubkey,
+        payment_mint: Option<&Pubkey>,
+        amount: Option<u64>,
+    ) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        if escrow.arbitration.is_some() || escrow.beneficiary.is_some() {
+            return Err(CliError::NotASwap(*escrow_address));
+        }
+        let taker = self.payer.pubkey();
+        let payment_mint = payment_mint.unwrap_or(&escrow.mint_b);
+        // An auction never asks more than its start price, so that caps a
+        // take of everything at whatever the price has fallen to.
+        let receive_amount = match escrow.auction {
+            Some(auction) if *payment_mint == escrow.mint_b => auction.start_price,
+            _ => escrow
+                .receive_amount_in(payment_mint)
+                .ok_or(CliError::PaymentMintNotAccepted(*payment_mint))?,
+        };
+        let token_program_a = self.asset_program(&escrow.mint_a)?;
+        let token_program_b = self.asset_program(payment_mint)?;
+        let maker_token_b = asset_account(&escrow.owner, payment_mint, &token_program_b);
+        // Without a config no fee is charged and the treasury account is
+        // never read, so the maker's account stands in for it.
+        let treasury_token_b = match self.config()? {
+            Some(config) => asset_account(&config.treasury, payment_mint, &token_program_b),
+            None => maker_token_b,
+        };
+
+        let ix = instruction_builders::take_paying_with(
+            &self.program_id,
+            &TakeAccounts {
+                taker,
+                maker: escrow.owner,
+                mint_a: escrow.mint_a,
+                mint_b: escrow.mint_b,
+                taker_token_a: asset_account(&taker, &escrow.mint_a, &token_program_a),
+                taker_token_b: asset_account(&taker, payment_mint, &token_program_b),
+                maker_token_b,
+                treasury_token_b,
+                token_program_a,
+                token_program_b,
+            },
+            payment_mint,
+            escrow.seed,
+            amount.unwrap_or(receive_amount),
+        );
+        let ix = self.with_basket(ix, escrow_address, &escrow, &taker)?;
+        self.rpc.send(&[with_rent_payer(ix, &escrow)], &self.payer)
+    }
+
+    /// Re-prices an escrow the payer made, or changes its deposit or expiry.
+    pub fn update(&self, escrow_address: &Pubkey, args: UpdateArgs) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let maker = self.payer.pubkey();
+        if escrow.owner != maker {
+            return Err(CliError::NotMaker(*escrow_address));
+        }
+        let token_program = self.asset_program(&escrow.mint_a)?;
+
+        let ix = instruction_builders::update(
+            &self.program_id,
+            &UpdateAccounts {
+                maker,
+                mint_a: escrow.mint_a,
+                mint_b: escrow.mint_b,
+                maker_token_a: asset_account(&maker, &escrow.mint_a, &token_program),
+                token_program,
+            },
+            escrow.seed,
+            args,
+        );
+        self.rpc.send(&[ix], &self.payer)
+    }
+
+    /// Accepts `mint` as payment for an escrow the payer made, at
+    /// `receive_amount`; zero stops accepting it. A new mint on a sponsored
+    /// escrow needs its rent payer to sign, so it is rejected here.
+    pub fn set_payment_mint(
+        &self,
+        escrow_address: &Pubkey,
+        mint: &Pubkey,
+        receive_amount: u64,
+    ) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let maker = self.payer.pubkey();
+        if escrow.owner != maker {
+            return Err(CliError::NotMaker(*escrow_address));
+        }
+
+        let ix = instruction_builders::set_payment_mint(
+            &self.program_id,
+            &maker,
+            escrow_address,
+            mint,
+            receive_amount,
+        );
+        self.rpc.send(&[ix], &self.payer)
+    }
+
+    /// Sells mint B of an escrow the payer made by Dutch auction, or returns
+    /// it to its fixed price when `auction` is `None`.
+    pub fn set_auction(
+        &self,
+        escrow_address: &Pubkey,
+        auction: Option<Auction>,
+    ) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let maker = self.payer.pubkey();
+        if escrow.owner != maker {
+            return Err(CliError::NotMaker(*escrow_address));
+        }
+
+        let ix =
+            instruction_builders::set_auction(&self.program_id, &maker, escrow_address, auction);
+        self.rpc.send(&[ix], &self.payer)
+    }
+
+    /// Offers `args.amount_offered` of `mint_a` split into `milestones`, each
+    /// paid to `beneficiary` when the payer approves it. The milestone amounts
+    /// must add up to the deposit. The price in `args` is ignored; there is
+    /// no mint B.
+    pub fn make_with_milestones(
+        &self,
+        mint_a: &Pubkey,
+        args: MakeArgs,
+        beneficiary: &Pubkey,
+        milestones: Vec<MilestoneArgs>,
+    ) -> Result<(Pubkey, Signature), CliError> {
+        let kind = EscrowKind::Milestones {
+            beneficiary: *beneficiary,
+            milestones,
+        };
+        self.make_payment(mint_a, args, kind)
+    }
+
+    /// Pays milestone `index` of an escrow the payer made to its beneficiary.
+    pub fn approve_milestone(
+        &self,
+        escrow_address: &Pubkey,
+        index: u8,
+    ) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let maker = self.payer.pubkey();
+        if escrow.owner != maker {
+            return Err(CliError::NotMaker(*escrow_address));
+        }
+        let beneficiary = escrow
+            .beneficiary
+            .ok_or(CliError::NoMilestones(*escrow_address))?;
+        let token_program = self.asset_program(&escrow.mint_a)?;
+
+        let ix = instruction_builders::approve_milestone(
+            &self.program_id,
+            &ApproveMilestoneAccounts {
+                maker,
+                mint_a: escrow.mint_a,
+                beneficiary_token_a: asset_account(&beneficiary, &escrow.mint_a, &token_program),
+                token_program,
+            },
+            escrow.seed,
+            index,
+        );
+        self.rpc.send(&[with_rent_payer(ix, &escrow)], &self.payer)
+    }
+
+    /// Settles an arbitrated escrow, paying `seller_amount` to the seller and
+    /// the rest back to the buyer. The payer must be the arbiter, or the buyer
+    /// releasing everything, or the seller refunding everything.
+    pub fn settle(
+        &self,
+        escrow_address: &Pubkey,
+        seller_amount: u64,
+    ) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let arbitration = escrow
+            .arbitration
+            .ok_or(CliError::NotArbitrated(*escrow_address))?;
+        let token_program = self.asset_program(&escrow.mint_a)?;
+
+        let ix = instruction_builders::settle(
+            &self.program_id,
+            &SettleAccounts {
+                signer: self.payer.pubkey(),
+                maker: escrow.owner,
+                mint_a: escrow.mint_a,
+                seller_token_a: asset_account(&arbitration.seller, &escrow.mint_a, &token_program),
+                maker_token_a: asset_account(&escrow.owner, &escrow.mint_a, &token_program),
+                token_program,
+            },
+            escrow.seed,
+            seller_amount,
+        );
+        self.rpc.send(&[with_rent_payer(ix, &escrow)], &self.payer)
+    }
+
+    /// Returns the offered tokens to the maker and closes the escrow.
+    pub fn refund(&self, escrow_address: &Pubkey) -> Result<Signature, CliError> {
+        let escrow = self.show(escrow_address)?;
+        let maker = self.payer.pubkey();
+        if escrow.owner != maker {
+            return Err(CliError::NotMaker(*escrow_address));
+        }
+        let token_program = self.asset_program(&escrow.mint_a)?;
+
+        let ix = instruction_builders::refund(
+            &self.program_id,
+            &maker,
+            &escrow.mint_a,
+            &escrow.mint_b,
+            &asset_account(&maker, &escrow.mint_a, &token_program),
+            &token_program,
+            escrow.seed,
+        );
+        let ix = self.with_basket(ix, escrow_address, &escrow, &maker)?;
+        self.rpc.send(&[with_rent_payer(ix, &escrow)], &self.payer)
+    }
+
+    pub fn show(&self, escrow_address: &Pubkey) -> Result<Escrow, CliError> {
+        let account = self
+            .rpc
+            .get_account(escrow_address)?
+            .ok_or(CliError::AccountNotFound(*escrow_address))?;
+        if account.owner != self.program_id {
+            return Err(CliError::NotAnEscrow(*escrow_address));
+        }
+        Escrow::unpack(&account.data).map_err(|_| CliError::NotAnEscrow(*escrow_address))
+    }
+
+    /// Lists open escrows, optionally only those made by `maker`.
+    pub fn list(&self, maker: Option<&Pubkey>) -> Result<Vec<(Pubkey, Escrow)>, CliError> {
+        let mut escrows: Vec<(Pubkey, Escrow)> = self
+            .rpc
+            .get_escrow_accounts(&self.program_id)?
+            .into_iter()
+            .filter_map(|(address, account)| {
+                Escrow::unpack(&account.data)
+                    .ok()
+                    .map(|escrow| (address, escrow))
+            })
+            .filter(|(_, escrow)| maker.is_none_or(|maker| escrow.owner == *maker))
+            .collect();
+        escrows.sort_by_key(|(address, _)| *address);
+        Ok(escrows)
+    }
+
+    /// The protocol config, or `None` if the admin has not created it yet.
+    fn config(&self) -> Result<Option<Config>, CliError> {
+        let (address, _) = instruction_builders::find_config_address(&self.program_id);
+        Ok(self
+            .rpc
+            .get_account(&address)?
+            .filter(|account| account.owner == self.program_id)
+            .and_then(|account| Config::unpack(&account.data).ok()))
+    }
+
+    /// Appends the escrow's basket legs, paid out to `recipient`'s associated
+    /// token accounts.
+    fn with_basket(
+        &self,
+        ix: Instruction,
+        escrow_address: &Pubkey,
+        escrow: &Escrow,
+        recipient: &Pubkey,
+    ) -> Result<Instruction, CliError> {
+        let legs = escrow
+            .basket
+            .iter()
+            .map(|leg| self.basket_leg(recipient, &leg.mint))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(instruction_builders::with_basket(
+            &self.program_id,
+            ix,
+            escrow_address,
+            &legs,
+        ))
+    }
+
+    fn basket_leg(&self, owner: &Pubkey, mint: &Pubkey) -> Result<BasketLegAccounts, CliError> {
+        let token_program = self.asset_program(mint)?;
+        Ok(BasketLegAccounts {
+            mint: *mint,
+            token_account: get_associated_token_address_with_program_id(
+                owner,
+                mint,
+                &token_program,
+            ),
+            token_program,
+        })
+    }
+
+    /// Makes an escrow of `kind` that pays out mint A without ever being
+    /// swapped, so it has no mint B or price.
+    fn make_payment(
+        &self,
+        mint_a: &Pubkey,
+        mut args: MakeArgs,
+        kind: EscrowKind,
+    ) -> Result<(Pubkey, Signature), CliError> {
+        let maker = self.payer.pubkey();
+        let token_program = self.asset_program(mint_a)?;
+        let no_mint_b = Pubkey::default();
+        let (escrow, _) = instruction_builders::find_escrow_address(
+            &self.program_id,
+            &maker,
+            mint_a,
+            &no_mint_b,
+            args.seed,
+        );
+
+        args.amount_required = 0;
+        args.kind = kind;
+        let ix = instruction_builders::make(
+            &self.program_id,
+            &maker,
+            mint_a,
+            &no_mint_b,
+            &asset_account(&maker, mint_a, &token_program),
+            &token_program,
+            args,
+        );
+        let signature = self.rpc.send(&[ix], &self.payer)?;
+        Ok((escrow, signature))
+    }
+
+    /// The program that moves `mint`: the system program for native SOL,
+    /// otherwise whichever token program owns the mint.
+    fn asset_program(&self, mint: &Pubkey) -> Result<Pubkey, CliError> {
+        if *mint == native_mint::ID {
+            return Ok(system_program::ID);
+        }
+        let account = self
+            .rpc
+            .get_account(mint)?
+            .ok_or(CliError::AccountNotFound(*mint))?;
+        Ok(account.owner)
+    }
+}
+
+/// Passes a sponsored escrow's rent payer so it gets its rent back if the
+/// instruction closes the escrow.
+fn with_rent_payer(ix: Instruction, escrow: &Escrow) -> Instruction {
+    if escrow.rent_payer == escrow.owner {
+        return ix;
+    }
+    instruction_builders::return_rent_to(ix, &escrow.rent_payer)
+}
+
+/// The account holding `owner`'s side of a trade: the wallet itself for
+/// native SOL, otherwise its associated token account.
+fn asset_account(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    if *mint == native_mint::ID {
+        return *owner;
+    }
+    get_associated_token_address_with_program_id(owner, mint, token_program)
+}
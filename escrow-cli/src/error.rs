@@ -0,0 +1,69 @@
+use escrow::error::EscrowError;
+use solana_instruction::{error::InstructionError, Instruction};
+use solana_pubkey::Pubkey;
+use solana_transaction_error::TransactionError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("RPC request failed: {0}")]
+    Rpc(String),
+    #[error("Escrow program rejected the transaction: {0}")]
+    Program(EscrowError),
+    #[error("Account {0} does not exist")]
+    AccountNotFound(Pubkey),
+    #[error("Account {0} is not an escrow owned by this program")]
+    NotAnEscrow(Pubkey),
+    #[error("Escrow {0} was not made by the signing keypair")]
+    NotMaker(Pubkey),
+    #[error("Escrow does not accept payment in {0}")]
+    PaymentMintNotAccepted(Pubkey),
+    #[error("Escrow {0} is a payment, not a swap")]
+    NotASwap(Pubkey),
+    #[error("Escrow {0} is not arbitrated")]
+    NotArbitrated(Pubkey),
+    #[error("Escrow {0} has no milestones")]
+    NoMilestones(Pubkey),
+}
+
+impl CliError {
+    /// Maps a failed transaction of `instructions` to the escrow error that
+    /// caused it. A custom error is only an escrow error when the logs show
+    /// the escrow program raised it itself; one raised by the token program
+    /// in a CPI reuses the same codes for other failures. Anything else falls
+    /// back to `message` and the logs.
+    pub fn from_transaction_error(
+        instructions: &[Instruction],
+        error: Option<&TransactionError>,
+        logs: &[String],
+        message: String,
+    ) -> Self {
+        if let Some(TransactionError::InstructionError(index, InstructionError::Custom(code))) =
+            error
+        {
+            // A failing CPI is logged before the programs that invoked it.
+            let raised_by_escrow = instructions.get(*index as usize).is_some_and(|ix| {
+                logs.iter()
+                    .find(|line| line.contains(" failed: "))
+                    .is_some_and(|line| {
+                        *line
+                            == format!(
+                                "Program {} failed: {}",
+                                ix.program_id,
+                                InstructionError::Custom(*code)
+                            )
+                    })
+            });
+            if raised_by_escrow {
+                if let Ok(error) = EscrowError::try_from(*code) {
+                    return CliError::Program(error);
+                }
+            }
+        }
+        if logs.is_empty() {
+            CliError::Rpc(message)
+        } else {
+            CliError::Rpc(format!("{message}\n{}", logs.join("\n")))
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    num::NonZeroU64,
+    path::PathBuf,
+    process::ExitCode,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Parser, Subcommand};
+use escrow::{
+    instructions::{EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{Auction, Escrow, FeeMode},
+};
+use escrow_cli::client::EscrowClient;
+use solana_commitment_config::CommitmentConfig;
+use solana_keypair::read_keypair_file;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::rpc_client::RpcClient;
+
+#[derive(Parser)]
+#[command(name = "escrow-cli", about = "Make, take and refund token escrows")]
+struct Cli {
+    /// JSON RPC endpoint of the cluster
+    #[arg(long, default_value = "http://localhost:8899")]
+    url: String,
+
+    /// Keypair file that signs and pays for transactions
+    #[arg(long, default_value_os_t = default_keypair_path())]
+    keypair: PathBuf,
+
+    /// Address the escrow program is deployed at
+    #[arg(long)]
+    program_id: Pubkey,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Offer mint A in exchange for mint B
+    Make {
+        #[arg(long)]
+        mint_a: Pubkey,
+        #[arg(long, required_unless_present_any = ["arbiter", "beneficiary"])]
+        mint_b: Option<Pubkey>,
+        /// Amount of mint A to deposit
+        #[arg(long)]
+        amount: u64,
+        /// Amount of mint B asked for in return
+        #[arg(long, required_unless_present_any = ["arbiter", "beneficiary"])]
+        receive: Option<NonZeroU64>,
+        /// Distinguishes escrows between the same maker and mints; defaults
+        /// to the current unix time
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Unix timestamp after which the escrow can no longer be taken
+        #[arg(long)]
+        expires_at: Option<i64>,
+        /// Only this wallet may take the escrow
+        #[arg(long)]
+        taker: Option<Pubkey>,
+        /// Require `receive` to arrive after mint B transfer fees
+        #[arg(long)]
+        net: bool,
+        /// Also deposit AMOUNT of MINT, released only with the whole escrow;
+        /// may be repeated
+        #[arg(long, value_name = "MINT:AMOUNT", value_parser = parse_basket_leg)]
+        basket: Vec<(Pubkey, u64)>,
+        /// Hold the deposit as a payment to `--seller` that only this key, or
+        /// a concession by either side, can settle
+        #[arg(
+            long,
+            requires = "seller",
+            conflicts_with_all = ["basket", "mint_b", "receive", "expires_at", "taker", "net"]
+        )]
+        arbiter: Option<Pubkey>,
+        /// Who an arbitrated payment is for
+        #[arg(long, requires = "arbiter")]
+        seller: Option<Pubkey>,
+        /// Pay the deposit to `--beneficiary` in milestones that you approve
+        /// one by one; the amounts must add up to `--amount`. May be repeated
+        #[arg(
+            long,
+            value_name = "NAME:AMOUNT",
+            value_parser = parse_milestone,
+            requires = "beneficiary",
+            conflicts_with_all = ["basket", "arbiter"]
+        )]
+        milestone: Vec<MilestoneArgs>,
+        /// Who milestones are paid to
+        #[arg(
+            long,
+            requires = "milestone",
+            conflicts_with_all = ["mint_b", "receive", "expires_at", "taker", "net"]
+        )]
+        beneficiary: Option<Pubkey>,
+    },
+    /// Fill an escrow, fully unless `--amount` is given
+    Take {
+        escrow: Pubkey,
+        /// Amount of mint B to pay
+        #[arg(long)]
+        amount: Option<u64>,
+        /// Pay in another mint the escrow accepts instead of mint B
+        #[arg(long)]
+        pay_with: Option<Pubkey>,
+    },
+    /// Change the terms of an escrow you made
+    Update {
+        escrow: Pubkey,
+        /// New amount of mint B asked for; end any auction first
+        #[arg(long)]
+        receive: Option<u64>,
+        /// Amount of mint A to add to the deposit
+        #[arg(long, default_value_t = 0, conflicts_with = "withdraw")]
+        deposit: u64,
+        /// Amount of mint A to take back from the deposit
+        #[arg(long, default_value_t = 0)]
+        withdraw: u64,
+        /// New unix timestamp after which the escrow can no longer be taken
+        #[arg(long, conflicts_with = "no_expiry")]
+        expires_at: Option<i64>,
+        /// Remove the expiry
+        #[arg(long)]
+        no_expiry: bool,
+    },
+    /// Accept another mint as payment for an escrow you made
+    Accept {
+        escrow: Pubkey,
+        #[arg(long)]
+        mint: Pubkey,
+        /// Amount of the mint asked for the whole remaining deposit; 0 stops
+        /// accepting it
+        #[arg(long)]
+        receive: u64,
+    },
+    /// Sell mint B of an escrow you made by Dutch auction
+    Auction {
+        escrow: Pubkey,
+        /// Amount of mint B asked for the whole remaining deposit at the start
+        #[arg(long, required_unless_present = "stop")]
+        start_price: Option<u64>,
+        /// Amount of mint B asked once the auction ends
+        #[arg(long, required_unless_present = "stop")]
+        end_price: Option<u64>,
+        /// Unix timestamp the price starts falling at; defaults to now
+        #[arg(long)]
+        start_at: Option<i64>,
+        /// Unix timestamp the price reaches `end_price` at
+        #[arg(long, required_unless_present = "stop")]
+        end_at: Option<i64>,
+        /// End the auction and return to the fixed price
+        #[arg(long, conflicts_with_all = ["start_price", "end_price", "start_at", "end_at"])]
+        stop: bool,
+    },
+    /// Pay a milestone of an escrow you made to its beneficiary
+    Approve {
+        escrow: Pubkey,
+        /// Position of the milestone, counting from 0 as listed by `show`
+        #[arg(long)]
+        milestone: u8,
+    },
+    /// Rule on an arbitrated escrow as its arbiter, buyer or seller
+    Settle {
+        escrow: Pubkey,
+        /// Amount of the deposit paid to the seller; the rest goes back to the
+        /// buyer. The buyer can only release it all and the seller only refund
+        /// it all
+        #[arg(long)]
+        to_seller: u64,
+    },
+    /// Cancel an escrow you made and recover the deposit
+    Refund { escrow: Pubkey },
+    /// Print a single escrow
+    Show { escrow: Pubkey },
+    /// Print all open escrows
+    List {
+        /// Only escrows made by this wallet
+        #[arg(long)]
+        maker: Option<Pubkey>,
+    },
+}
+
+fn parse_basket_leg(leg: &str) -> Result<(Pubkey, u64), String> {
+    let (mint, amount) = leg
+        .split_once(':')
+        .ok_or_else(|| format!("expected MINT:AMOUNT, got `{leg}`"))?;
+    let mint = mint
+        .parse()
+        .map_err(|e| format!("invalid mint `{mint}`: {e}"))?;
+    let amount = amount
+        .parse()
+        .map_err(|e| format!("invalid amount `{amount}`: {e}"))?;
+    Ok((mint, amount))
+}
+
+fn parse_milestone(milestone: &str) -> Result<MilestoneArgs, String> {
+    let (name, amount) = milestone
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected NAME:AMOUNT, got `{milestone}`"))?;
+    let amount = amount
+        .parse()
+        .map_err(|e| format!("invalid amount `{amount}`: {e}"))?;
+    Ok(MilestoneArgs {
+        name: name.to_string(),
+        amount,
+    })
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn default_keypair_path() -> PathBuf {
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".config/solana/id.json")
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let payer = read_keypair_file(&cli.keypair)
+        .map_err(|e| format!("failed to read keypair {}: {e}", cli.keypair.display()))?;
+    let rpc = RpcClient::new_with_commitment(cli.url, CommitmentConfig::confirmed());
+    let client = EscrowClient::new(rpc, cli.program_id, payer);
+
+    match cli.command {
+        Command::Make {
+            mint_a,
+            mint_b,
+            amount,
+            receive,
+            seed,
+            expires_at,
+            taker,
+            net,
+            basket,
+            arbiter,
+            seller,
+            milestone,
+            beneficiary,
+        } => {
+            let seed = seed.unwrap_or_else(|| unix_now() as u64);
+            let args = MakeArgs {
+                seed,
+                amount_offered: amount,
+                amount_required: receive.map_or(0, NonZeroU64::get),
+                expires_at,
+                allowed_taker: taker,
+                receive_fee_mode: if net { FeeMode::Net } else { FeeMode::Gross },
+                basket: Vec::new(),
+                kind: EscrowKind::Swap,
+            };
+            let (escrow, signature) = match (arbiter.zip(seller), beneficiary, mint_b) {
+                (Some((arbiter, seller)), _, _) => {
+                    client.make_arbitrated(&mint_a, args, &arbiter, &seller)?
+                }
+                (None, Some(beneficiary), _) => {
+                    client.make_with_milestones(&mint_a, args, &beneficiary, milestone)?
+                }
+                (None, None, Some(mint_b)) => client.make(&mint_a, &mint_b, args, &basket)?,
+                // clap requires --mint-b for a swap.
+                (None, None, None) => unreachable!(),
+            };
+            println!("Escrow: {escrow}");
+            println!("Signature: {signature}");
+        }
+        Command::Take {
+            escrow,
+            amount,
+            pay_with,
+        } => {
+            let signature = client.take_paying_with(&escrow, pay_with.as_ref(), amount)?;
+            println!("Signature: {signature}");
+        }
+        Command::Update {
+            escrow,
+            receive,
+            deposit,
+            withdraw,
+            expires_at,
+            no_expiry,
+        } => {
+            let expires_at = if no_expiry {
+                Some(None)
+            } else {
+                expires_at.map(Some)
+            };
+            let args = UpdateArgs {
+                receive_amount: receive,
+                deposit,
+                withdraw,
+                expires_at,
+            };
+            println!("Signature: {}", client.update(&escrow, args)?);
+        }
+        Command::Accept {
+            escrow,
+            mint,
+            receive,
+        } => {
+            println!(
+                "Signature: {}",
+                client.set_payment_mint(&escrow, &mint, receive)?
+            );
+        }
+        Command::Auction {
+            escrow,
+            start_price,
+            end_price,
+            start_at,
+            end_at,
+            stop,
+        } => {
+            let auction = match (start_price, end_price, end_at) {
+                (Some(start_price), Some(end_price), Some(end_at)) if !stop => Some(Auction {
+                    start_price,
+                    end_price,
+                    start_at: start_at.unwrap_or_else(unix_now),
+                    end_at,
+                }),
+                _ => None,
+            };
+            println!("Signature: {}", client.set_auction(&escrow, auction)?);
+        }
+        Command::Approve { escrow, milestone } => {
+            println!(
+                "Signature: {}",
+                client.approve_milestone(&escrow, milestone)?
+            );
+        }
+        Command::Settle { escrow, to_seller } => {
+            println!("Signature: {}", client.settle(&escrow, to_seller)?);
+        }
+        Command::Refund { escrow } => {
+            println!("Signature: {}", client.refund(&escrow)?);
+        }
+        Command::Show { escrow } => {
+            print_escrow(&escrow, &client.show(&escrow)?);
+        }
+        Command::List { maker } => {
+            let escrows = client.list(maker.as_ref())?;
+            if escrows.is_empty() {
+                println!("No open escrows");
+            }
+            for (address, escrow) in &escrows {
+                print_escrow(address, escrow);
+                println!();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_escrow(address: &Pubkey, escrow: &Escrow) {
+    println!("Escrow: {address}");
+    println!("  Maker: {}", escrow.owner);
+    if escrow.rent_payer != escrow.owner {
+        println!("  Rent payer: {}", escrow.rent_payer);
+    }
+    println!("  Seed: {}", escrow.seed);
+    println!("  Offers: {} of {}", escrow.amount, escrow.mint_a);
+    for leg in &escrow.basket {
+        println!("    and {} of {}", leg.amount, leg.mint);
+    }
+    match escrow.auction {
+        // Payments have no mint B to ask for.
+        _ if escrow.mint_b == Pubkey::default() => {}
+        Some(auction) => println!(
+            "  Wants: {} falling to {} of {} between {} and {}",
+            auction.start_price, auction.end_price, escrow.mint_b, auction.start_at, auction.end_at
+        ),
+        None => println!("  Wants: {} of {}", escrow.receive_amount, escrow.mint_b),
+    }
+    for option in &escrow.payment_options {
+        println!("    or {} of {}", option.receive_amount, option.mint);
+    }
+    if let Some(expires_at) = escrow.expires_at {
+        println!("  Expires at: {expires_at}");
+    }
+    if let Some(allowed_taker) = escrow.allowed_taker {
+        println!("  Taker: {allowed_taker}");
+    }
+    if let Some(arbitration) = escrow.arbitration {
+        println!("  Arbiter: {}", arbitration.arbiter);
+        println!("  Seller: {}", arbitration.seller);
+    }
+    if let Some(beneficiary) = escrow.beneficiary {
+        println!("  Beneficiary: {beneficiary}");
+    }
+    for (index, milestone) in escrow.milestones.iter().enumerate() {
+        let status = if milestone.released {
+            "released"
+        } else {
+            "pending"
+        };
+        println!(
+            "    [{index}] {}: {} ({status})",
+            milestone.name, milestone.amount
+        );
+    }
+    if escrow.receive_fee_mode == FeeMode::Net {
+        println!("  Receives net of transfer fees");
+    }
+}
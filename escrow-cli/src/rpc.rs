@@ -0,0 +1,102 @@
+use escrow::state::{AccountType, Escrow};
+use solana_account::Account;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_commitment_config::CommitmentConfig;
+use solana_instruction::Instruction;
+use solana_keypair::Keypair;
+use solana_pubkey::Pubkey;
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client_api::{
+    client_error::ErrorKind as ClientErrorKind,
+    config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    filter::{Memcmp, RpcFilterType},
+    request::{RpcError, RpcResponseErrorData},
+};
+use solana_signature::Signature;
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+
+use crate::error::CliError;
+
+/// The chain access the client needs. Implemented for `RpcClient`; tests
+/// implement it on top of LiteSVM.
+pub trait EscrowRpc {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, CliError>;
+
+    /// Returns every escrow account owned by `program_id`.
+    fn get_escrow_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Account)>, CliError>;
+
+    /// Signs `instructions` with `payer` against a fresh blockhash and sends
+    /// them as a single transaction.
+    fn send(&self, instructions: &[Instruction], payer: &Keypair) -> Result<Signature, CliError>;
+}
+
+impl EscrowRpc for RpcClient {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, CliError> {
+        self.get_account_with_commitment(pubkey, self.commitment())
+            .map(|response| response.value)
+            .map_err(|e| CliError::Rpc(e.to_string()))
+    }
+
+    fn get_escrow_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Account)>, CliError> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                // Escrows are sized to fit, so match the account type and
+                // layout version rather than the data size.
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    0,
+                    &[AccountType::Escrow as u8, Escrow::VERSION],
+                )),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(CommitmentConfig::confirmed()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        self.get_program_accounts_with_config(program_id, config)
+            .map_err(|e| CliError::Rpc(e.to_string()))
+    }
+
+    fn send(&self, instructions: &[Instruction], payer: &Keypair) -> Result<Signature, CliError> {
+        let blockhash = self
+            .get_latest_blockhash()
+            .map_err(|e| CliError::Rpc(e.to_string()))?;
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&payer.pubkey()),
+            &[payer],
+            blockhash,
+        );
+        self.send_and_confirm_transaction(&tx).map_err(|e| {
+            let logs = match e.kind() {
+                ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                    data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+                    ..
+                }) => result.logs.clone().unwrap_or_default(),
+                _ => Vec::new(),
+            };
+            CliError::from_transaction_error(
+                instructions,
+                e.get_transaction_error().as_ref(),
+                &logs,
+                e.to_string(),
+            )
+        })
+    }
+}
+
+impl<T: EscrowRpc + ?Sized> EscrowRpc for &T {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, CliError> {
+        (**self).get_account(pubkey)
+    }
+
+    fn get_escrow_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Account)>, CliError> {
+        (**self).get_escrow_accounts(program_id)
+    }
+
+    fn send(&self, instructions: &[Instruction], payer: &Keypair) -> Result<Signature, CliError> {
+        (**self).send(instructions, payer)
+    }
+}
@@ -0,0 +1,498 @@
+use std::{cell::RefCell, collections::BTreeSet};
+
+use escrow::{
+    error::EscrowError,
+    instructions::{EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{AccountType, Auction, Escrow, FeeMode},
+};
+use escrow_cli::{client::EscrowClient, error::CliError, rpc::EscrowRpc};
+use litesvm::LiteSVM;
+use litesvm_token::{get_spl_account, spl_token, CreateAssociatedTokenAccount, CreateMint, MintTo};
+use solana_account::Account;
+use solana_clock::Clock;
+use solana_instruction::{error::InstructionError, Instruction};
+use solana_keypair::Keypair;
+use solana_pubkey::{pubkey, Pubkey};
+use solana_signature::Signature;
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+use solana_transaction_error::TransactionError;
+
+const PROGRAM_ID: Pubkey = pubkey!("DinjxyZz2tjZTVi5FbKNoi2aayH71Q3EMzEh8yiGJnVY");
+const SEED: u64 = 42;
+
+/// Serves the client from an in-process LiteSVM. LiteSVM cannot scan program
+/// accounts, so every account touched by a sent transaction is remembered and
+/// searched instead.
+struct LiteSvmRpc {
+    svm: RefCell<LiteSVM>,
+    touched: RefCell<BTreeSet<Pubkey>>,
+}
+
+impl EscrowRpc for LiteSvmRpc {
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, CliError> {
+        Ok(self.svm.borrow().get_account(pubkey))
+    }
+
+    fn get_escrow_accounts(&self, program_id: &Pubkey) -> Result<Vec<(Pubkey, Account)>, CliError> {
+        let svm = self.svm.borrow();
+        Ok(self
+            .touched
+            .borrow()
+            .iter()
+            .filter_map(|address| Some((*address, svm.get_account(address)?)))
+            .filter(|(_, account)| {
+                account.owner == *program_id
+                    && account
+                        .data
+                        .starts_with(&[AccountType::Escrow as u8, Escrow::VERSION])
+            })
+            .collect())
+    }
+
+    fn send(&self, instructions: &[Instruction], payer: &Keypair) -> Result<Signature, CliError> {
+        let mut svm = self.svm.borrow_mut();
+        let tx = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&payer.pubkey()),
+            &[payer],
+            svm.latest_blockhash(),
+        );
+        self.touched
+            .borrow_mut()
+            .extend(tx.message.account_keys.iter().copied());
+        let signature = tx.signatures[0];
+        svm.send_transaction(tx).map_err(|e| {
+            CliError::from_transaction_error(
+                instructions,
+                Some(&e.err),
+                &e.meta.logs,
+                format!("{:?}", e.err),
+            )
+        })?;
+        Ok(signature)
+    }
+}
+
+/// Returns the mock RPC with a maker holding 100 of mint A and a taker
+/// holding 50 of mint B, each in their associated token accounts.
+fn setup() -> (LiteSvmRpc, Keypair, Keypair, Pubkey, Pubkey) {
+    let mut svm = LiteSVM::new();
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+    svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
+
+    let mint_a = CreateMint::new(&mut svm, &maker).send().unwrap();
+    let mint_b = CreateMint::new(&mut svm, &maker).send().unwrap();
+
+    for (owner, mint) in [
+        (&maker, mint_a),
+        (&maker, mint_b),
+        (&taker, mint_a),
+        (&taker, mint_b),
+    ] {
+        CreateAssociatedTokenAccount::new(&mut svm, owner, &mint)
+            .owner(&owner.pubkey())
+            .send()
+            .unwrap();
+    }
+
+    let maker_token_a =
+        spl_associated_token_account_interface::address::get_associated_token_address(
+            &maker.pubkey(),
+            &mint_a,
+        );
+    MintTo::new(&mut svm, &maker, &mint_a, &maker_token_a, 100)
+        .send()
+        .unwrap();
+    let taker_token_b =
+        spl_associated_token_account_interface::address::get_associated_token_address(
+            &taker.pubkey(),
+            &mint_b,
+        );
+    MintTo::new(&mut svm, &maker, &mint_b, &taker_token_b, 50)
+        .send()
+        .unwrap();
+
+    let rpc = LiteSvmRpc {
+        svm: RefCell::new(svm),
+        touched: RefCell::new(BTreeSet::new()),
+    };
+    (rpc, maker, taker, mint_a, mint_b)
+}
+
+fn make_args() -> MakeArgs {
+    MakeArgs {
+        seed: SEED,
+        amount_offered: 100,
+        amount_required: 50,
+        expires_at: None,
+        allowed_taker: None,
+        receive_fee_mode: FeeMode::Gross,
+        basket: Vec::new(),
+        kind: EscrowKind::Swap,
+    }
+}
+
+#[test]
+fn make_show_list_take() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let maker_pubkey = maker.pubkey();
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[])
+        .unwrap();
+
+    let escrow = taker_client.show(&escrow_address).unwrap();
+    assert_eq!(escrow.owner, maker_pubkey);
+    assert_eq!(escrow.amount, 100);
+    assert_eq!(escrow.receive_amount, 50);
+
+    let listed = taker_client.list(None).unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].0, escrow_address);
+    assert_eq!(taker_client.list(Some(&maker_pubkey)).unwrap().len(), 1);
+    assert!(taker_client
+        .list(Some(&Pubkey::new_unique()))
+        .unwrap()
+        .is_empty());
+
+    assert!(matches!(
+        taker_client.take(&escrow_address, Some(51)),
+        Err(CliError::Program(EscrowError::AmountExceedsRemaining))
+    ));
+
+    taker_client.take(&escrow_address, Some(20)).unwrap();
+    assert_eq!(
+        taker_client.show(&escrow_address).unwrap().receive_amount,
+        30
+    );
+
+    taker_client.take(&escrow_address, None).unwrap();
+    assert!(matches!(
+        taker_client.show(&escrow_address),
+        Err(CliError::AccountNotFound(_) | CliError::NotAnEscrow(_))
+    ));
+    assert!(taker_client.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn refund_only_by_maker() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[])
+        .unwrap();
+
+    assert!(matches!(
+        taker_client.refund(&escrow_address),
+        Err(CliError::NotMaker(_))
+    ));
+
+    maker_client.refund(&escrow_address).unwrap();
+    assert!(maker_client.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn update_reprices_escrow() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[])
+        .unwrap();
+    let reprice = || UpdateArgs {
+        receive_amount: Some(40),
+        deposit: 0,
+        withdraw: 20,
+        expires_at: None,
+    };
+
+    assert!(matches!(
+        taker_client.update(&escrow_address, reprice()),
+        Err(CliError::NotMaker(_))
+    ));
+
+    maker_client.update(&escrow_address, reprice()).unwrap();
+    let escrow = maker_client.show(&escrow_address).unwrap();
+    assert_eq!(escrow.amount, 80);
+    assert_eq!(escrow.receive_amount, 40);
+}
+
+#[test]
+fn basket_is_taken_whole() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let maker_pubkey = maker.pubkey();
+    let taker_pubkey = taker.pubkey();
+    let mint_c = {
+        let mut svm = rpc.svm.borrow_mut();
+        let mint_c = CreateMint::new(&mut svm, &maker).send().unwrap();
+        let maker_token_c = CreateAssociatedTokenAccount::new(&mut svm, &maker, &mint_c)
+            .owner(&maker_pubkey)
+            .send()
+            .unwrap();
+        MintTo::new(&mut svm, &maker, &mint_c, &maker_token_c, 30)
+            .send()
+            .unwrap();
+        mint_c
+    };
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[(mint_c, 30)])
+        .unwrap();
+    let escrow = taker_client.show(&escrow_address).unwrap();
+    assert_eq!(escrow.basket.len(), 1);
+    assert_eq!(escrow.basket[0].mint, mint_c);
+    assert_eq!(escrow.basket[0].amount, 30);
+
+    assert!(matches!(
+        taker_client.take(&escrow_address, Some(20)),
+        Err(CliError::Program(EscrowError::PartialFillNotAllowed))
+    ));
+    taker_client.take(&escrow_address, None).unwrap();
+
+    let taker_token_c =
+        spl_associated_token_account_interface::address::get_associated_token_address(
+            &taker_pubkey,
+            &mint_c,
+        );
+    let taker_account_c =
+        get_spl_account::<spl_token::state::Account>(&rpc.svm.borrow(), &taker_token_c).unwrap();
+    assert_eq!(taker_account_c.amount, 30);
+}
+
+#[test]
+fn take_in_accepted_payment_mint() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let taker_pubkey = taker.pubkey();
+    let mint_c = {
+        let mut svm = rpc.svm.borrow_mut();
+        let mint_c = CreateMint::new(&mut svm, &maker).send().unwrap();
+        let taker_token_c = CreateAssociatedTokenAccount::new(&mut svm, &taker, &mint_c)
+            .owner(&taker_pubkey)
+            .send()
+            .unwrap();
+        MintTo::new(&mut svm, &maker, &mint_c, &taker_token_c, 80)
+            .send()
+            .unwrap();
+        mint_c
+    };
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[])
+        .unwrap();
+    assert!(matches!(
+        taker_client.take_paying_with(&escrow_address, Some(&mint_c), None),
+        Err(CliError::PaymentMintNotAccepted(_))
+    ));
+    assert!(matches!(
+        taker_client.set_payment_mint(&escrow_address, &mint_c, 80),
+        Err(CliError::NotMaker(_))
+    ));
+
+    maker_client
+        .set_payment_mint(&escrow_address, &mint_c, 80)
+        .unwrap();
+    taker_client
+        .take_paying_with(&escrow_address, Some(&mint_c), None)
+        .unwrap();
+    assert!(taker_client.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn take_at_falling_auction_price() {
+    let (rpc, maker, taker, mint_a, mint_b) = setup();
+    let taker_token_b =
+        spl_associated_token_account_interface::address::get_associated_token_address(
+            &taker.pubkey(),
+            &mint_b,
+        );
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let taker_client = EscrowClient::new(&rpc, PROGRAM_ID, taker);
+    let now = rpc.svm.borrow().get_sysvar::<Clock>().unix_timestamp;
+
+    let (escrow_address, _) = maker_client
+        .make(&mint_a, &mint_b, make_args(), &[])
+        .unwrap();
+    let auction = Auction {
+        start_price: 80,
+        end_price: 20,
+        start_at: now,
+        end_at: now + 600,
+    };
+    assert!(matches!(
+        taker_client.set_auction(&escrow_address, Some(auction)),
+        Err(CliError::NotMaker(_))
+    ));
+    maker_client
+        .set_auction(&escrow_address, Some(auction))
+        .unwrap();
+    assert_eq!(
+        maker_client.show(&escrow_address).unwrap().auction,
+        Some(auction)
+    );
+
+    // Halfway down the price is 50; the taker's default cap of 80 is never
+    // charged in full.
+    {
+        let mut svm = rpc.svm.borrow_mut();
+        let mut clock = svm.get_sysvar::<Clock>();
+        clock.unix_timestamp = now + 300;
+        svm.set_sysvar(&clock);
+    }
+    taker_client.take(&escrow_address, None).unwrap();
+    let taker_b: spl_token::state::Account =
+        get_spl_account(&rpc.svm.borrow(), &taker_token_b).unwrap();
+    assert_eq!(taker_b.amount, 0);
+    assert!(taker_client.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn arbiter_splits_arbitrated_payment() {
+    let (rpc, maker, seller, mint_a, _mint_b) = setup();
+    let arbiter = Keypair::new();
+    rpc.svm
+        .borrow_mut()
+        .airdrop(&arbiter.pubkey(), 1_000_000_000)
+        .unwrap();
+    let arbiter_pubkey = arbiter.pubkey();
+    let seller_pubkey = seller.pubkey();
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let seller_client = EscrowClient::new(&rpc, PROGRAM_ID, seller);
+    let arbiter_client = EscrowClient::new(&rpc, PROGRAM_ID, arbiter);
+
+    let (escrow_address, _) = maker_client
+        .make_arbitrated(&mint_a, make_args(), &arbiter_pubkey, &seller_pubkey)
+        .unwrap();
+    assert!(matches!(
+        seller_client.take(&escrow_address, None),
+        Err(CliError::NotASwap(_))
+    ));
+    assert!(matches!(
+        seller_client.settle(&escrow_address, 100),
+        Err(CliError::Program(EscrowError::InvalidRuling))
+    ));
+
+    arbiter_client.settle(&escrow_address, 70).unwrap();
+    let seller_token_a: spl_token::state::Account = get_spl_account(
+        &rpc.svm.borrow(),
+        &spl_associated_token_account_interface::address::get_associated_token_address(
+            &seller_pubkey,
+            &mint_a,
+        ),
+    )
+    .unwrap();
+    assert_eq!(seller_token_a.amount, 70);
+    assert!(matches!(
+        arbiter_client.settle(&escrow_address, 70),
+        Err(CliError::AccountNotFound(_))
+    ));
+}
+
+#[test]
+fn approving_every_milestone_closes_escrow() {
+    let (rpc, maker, beneficiary, mint_a, _mint_b) = setup();
+    let beneficiary_pubkey = beneficiary.pubkey();
+    let maker_client = EscrowClient::new(&rpc, PROGRAM_ID, maker);
+    let beneficiary_client = EscrowClient::new(&rpc, PROGRAM_ID, beneficiary);
+    let beneficiary_token_a = || {
+        let account: spl_token::state::Account = get_spl_account(
+            &rpc.svm.borrow(),
+            &spl_associated_token_account_interface::address::get_associated_token_address(
+                &beneficiary_pubkey,
+                &mint_a,
+            ),
+        )
+        .unwrap();
+        account.amount
+    };
+
+    let milestones = vec![
+        MilestoneArgs {
+            name: "design".to_string(),
+            amount: 30,
+        },
+        MilestoneArgs {
+            name: "build".to_string(),
+            amount: 70,
+        },
+    ];
+    let (escrow_address, _) = maker_client
+        .make_with_milestones(&mint_a, make_args(), &beneficiary_pubkey, milestones)
+        .unwrap();
+    assert!(matches!(
+        beneficiary_client.take(&escrow_address, None),
+        Err(CliError::NotASwap(_))
+    ));
+    assert!(matches!(
+        beneficiary_client.approve_milestone(&escrow_address, 0),
+        Err(CliError::NotMaker(_))
+    ));
+
+    maker_client.approve_milestone(&escrow_address, 0).unwrap();
+    assert_eq!(beneficiary_token_a(), 30);
+    assert_eq!(maker_client.show(&escrow_address).unwrap().amount, 70);
+
+    maker_client.approve_milestone(&escrow_address, 1).unwrap();
+    assert_eq!(beneficiary_token_a(), 100);
+    assert!(maker_client.list(None).unwrap().is_empty());
+}
+
+#[test]
+fn only_escrow_program_errors_are_decoded() {
+    let ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: Vec::new(),
+        data: Vec::new(),
+    };
+    let error = TransactionError::InstructionError(0, InstructionError::Custom(1));
+    let token_program = spl_token::ID;
+
+    // The escrow program failing with code 1 is InvalidMint.
+    let logs = vec![
+        format!("Program {PROGRAM_ID} invoke [1]"),
+        format!("Program {PROGRAM_ID} failed: custom program error: 0x1"),
+    ];
+    assert!(matches!(
+        CliError::from_transaction_error(
+            std::slice::from_ref(&ix),
+            Some(&error),
+            &logs,
+            String::new()
+        ),
+        CliError::Program(EscrowError::InvalidMint)
+    ));
+
+    // The token program's InsufficientFunds, passed up through the escrow
+    // program, is not.
+    let logs = vec![
+        format!("Program {PROGRAM_ID} invoke [1]"),
+        format!("Program {token_program} invoke [2]"),
+        "Program log: Error: insufficient funds".to_string(),
+        format!("Program {token_program} failed: custom program error: 0x1"),
+        format!("Program {PROGRAM_ID} failed: custom program error: 0x1"),
+    ];
+    let CliError::Rpc(message) = CliError::from_transaction_error(
+        std::slice::from_ref(&ix),
+        Some(&error),
+        &logs,
+        "failed".to_string(),
+    ) else {
+        panic!("token program error decoded as an escrow error");
+    };
+    assert!(message.starts_with("failed\n"));
+    assert!(message.contains("insufficient funds"));
+}
@@ -1,7 +1,12 @@
-use solana_program_error::ProgramError;
+use num_enum::TryFromPrimitive;
+use solana_program_error::{ProgramError, ToStr};
 use thiserror::Error;
 
-#[derive(Debug, Error, Clone, Copy)]
+/// Errors returned by the escrow program as `ProgramError::Custom(code)`,
+/// where `code` is the variant's position. New variants go at the end so
+/// existing codes never change.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u32)]
 pub enum EscrowError {
     #[error("Invalid token amount")]
     InvalidAmount,
@@ -9,6 +14,106 @@ pub enum EscrowError {
     InvalidMint,
     #[error("Token account is not owned by the user")]
     InvalidUser,
+    #[error("Escrow has expired")]
+    EscrowExpired,
+    #[error("Escrow has not expired yet")]
+    EscrowNotExpired,
+    #[error("Signer is not the designated taker for this escrow")]
+    TakerNotAllowed,
+    #[error("Account data is not of the expected account type")]
+    InvalidAccountType,
+    #[error("Account data has an unsupported layout version")]
+    UnsupportedVersion,
+    #[error("Escrow account is not owned by the escrow program")]
+    InvalidEscrowOwner,
+    #[error("Escrow account does not match the derived escrow address")]
+    InvalidEscrowAddress,
+    #[error("Maker does not match the escrow owner")]
+    MakerMismatch,
+    #[error("Mint does not match the mint stored in the escrow")]
+    MintMismatch,
+    #[error("Vault does not match the derived vault address")]
+    InvalidVault,
+    #[error("Instruction data could not be decoded")]
+    InvalidInstruction,
+    #[error("A required signature is missing")]
+    MissingSignature,
+    #[error("Account is not the system program")]
+    InvalidSystemProgram,
+    #[error("Account is not the token program that owns the mint")]
+    InvalidTokenProgram,
+    #[error("Expiry must be in the future")]
+    InvalidExpiry,
+    #[error("Mint A and mint B must differ")]
+    SameMint,
+    #[error("Native SOL must be paid from and to the wallet itself")]
+    InvalidNativeAccount,
+    #[error("Token account is not owned by the token program")]
+    InvalidTokenAccount,
+    #[error("Account balance is too low")]
+    InsufficientFunds,
+    #[error("Escrow or vault account already exists")]
+    EscrowAlreadyExists,
+    #[error("Amount exceeds what the escrow still requires")]
+    AmountExceedsRemaining,
+    #[error("Fill is too small to release any tokens")]
+    FillTooSmall,
+    #[error("Arithmetic overflow")]
+    Overflow,
+    #[error("Signer is not the config admin")]
+    Unauthorized,
+    #[error("Fee exceeds the maximum allowed")]
+    FeeTooHigh,
+    #[error("Config account does not match the derived config address")]
+    InvalidConfig,
+    #[error("Treasury account does not belong to the configured treasury")]
+    InvalidTreasury,
+    #[error("Config account already exists")]
+    ConfigAlreadyExists,
+    #[error("Program is paused")]
+    ProgramPaused,
+    #[error("Token program is not allowed by the config")]
+    TokenProgramNotAllowed,
+    #[error("Too many allowed token programs")]
+    TooManyTokenPrograms,
+    #[error("Rent payer does not match the escrow's rent payer")]
+    InvalidRentPayer,
+    #[error("Account is not the associated token account program")]
+    InvalidAssociatedTokenProgram,
+    #[error("Basket mints must be SPL tokens distinct from the escrow's other mints")]
+    InvalidBasketMint,
+    #[error("Escrow basket is full")]
+    BasketFull,
+    #[error("Basket escrows must be filled in a single take")]
+    PartialFillNotAllowed,
+    #[error("Payment mint is mint B or already offered by the escrow")]
+    InvalidPaymentMint,
+    #[error("Too many payment mints")]
+    TooManyPaymentMints,
+    #[error("Auction price must fall over a period that has not ended yet")]
+    InvalidAuction,
+    #[error("Arbitrated escrows can only be settled by a ruling")]
+    Arbitrated,
+    #[error("Arbiter, buyer and seller must differ, with no mint B, price, basket, expiry, taker or net fee mode")]
+    InvalidArbitration,
+    #[error("Signer may not make this ruling")]
+    InvalidRuling,
+    #[error("Milestone escrows can only be approved or refunded")]
+    HasMilestones,
+    #[error("Milestones need short names and non-zero amounts adding up to the deposit, with no mint B, price, basket, expiry, taker or net fee mode")]
+    InvalidMilestones,
+    #[error("Too many milestones")]
+    TooManyMilestones,
+    #[error("Milestone does not exist or was already released")]
+    MilestoneUnavailable,
+    #[error("Account is not this program's program data")]
+    InvalidProgramData,
+    #[error("Signer is not the program's upgrade authority")]
+    NotUpgradeAuthority,
+    #[error("At least one token program must be allowed")]
+    NoTokenPrograms,
+    #[error("Mint B is priced by an auction; end it before setting a fixed price")]
+    AuctionActive,
 }
 
 impl From<EscrowError> for ProgramError {
@@ -16,3 +121,97 @@ impl From<EscrowError> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl ToStr for EscrowError {
+    fn to_str(&self) -> &'static str {
+        match self {
+            EscrowError::InvalidAmount => "Invalid token amount",
+            EscrowError::InvalidMint => "Token account does not match the required mint",
+            EscrowError::InvalidUser => "Token account is not owned by the user",
+            EscrowError::EscrowExpired => "Escrow has expired",
+            EscrowError::EscrowNotExpired => "Escrow has not expired yet",
+            EscrowError::TakerNotAllowed => "Signer is not the designated taker for this escrow",
+            EscrowError::InvalidAccountType => "Account data is not of the expected account type",
+            EscrowError::UnsupportedVersion => "Account data has an unsupported layout version",
+            EscrowError::InvalidEscrowOwner => "Escrow account is not owned by the escrow program",
+            EscrowError::InvalidEscrowAddress => {
+                "Escrow account does not match the derived escrow address"
+            }
+            EscrowError::MakerMismatch => "Maker does not match the escrow owner",
+            EscrowError::MintMismatch => "Mint does not match the mint stored in the escrow",
+            EscrowError::InvalidVault => "Vault does not match the derived vault address",
+            EscrowError::InvalidInstruction => "Instruction data could not be decoded",
+            EscrowError::MissingSignature => "A required signature is missing",
+            EscrowError::InvalidSystemProgram => "Account is not the system program",
+            EscrowError::InvalidTokenProgram => {
+                "Account is not the token program that owns the mint"
+            }
+            EscrowError::InvalidExpiry => "Expiry must be in the future",
+            EscrowError::SameMint => "Mint A and mint B must differ",
+            EscrowError::InvalidNativeAccount => {
+                "Native SOL must be paid from and to the wallet itself"
+            }
+            EscrowError::InvalidTokenAccount => "Token account is not owned by the token program",
+            EscrowError::InsufficientFunds => "Account balance is too low",
+            EscrowError::EscrowAlreadyExists => "Escrow or vault account already exists",
+            EscrowError::AmountExceedsRemaining => "Amount exceeds what the escrow still requires",
+            EscrowError::FillTooSmall => "Fill is too small to release any tokens",
+            EscrowError::Overflow => "Arithmetic overflow",
+            EscrowError::Unauthorized => "Signer is not the config admin",
+            EscrowError::FeeTooHigh => "Fee exceeds the maximum allowed",
+            EscrowError::InvalidConfig => {
+                "Config account does not match the derived config address"
+            }
+            EscrowError::InvalidTreasury => {
+                "Treasury account does not belong to the configured treasury"
+            }
+            EscrowError::ConfigAlreadyExists => "Config account already exists",
+            EscrowError::ProgramPaused => "Program is paused",
+            EscrowError::TokenProgramNotAllowed => "Token program is not allowed by the config",
+            EscrowError::TooManyTokenPrograms => "Too many allowed token programs",
+            EscrowError::InvalidRentPayer => "Rent payer does not match the escrow's rent payer",
+            EscrowError::InvalidAssociatedTokenProgram => {
+                "Account is not the associated token account program"
+            }
+            EscrowError::InvalidBasketMint => {
+                "Basket mints must be SPL tokens distinct from the escrow's other mints"
+            }
+            EscrowError::BasketFull => "Escrow basket is full",
+            EscrowError::PartialFillNotAllowed => "Basket escrows must be filled in a single take",
+            EscrowError::InvalidPaymentMint => {
+                "Payment mint is mint B or already offered by the escrow"
+            }
+            EscrowError::TooManyPaymentMints => "Too many payment mints",
+            EscrowError::InvalidAuction => {
+                "Auction price must fall over a period that has not ended yet"
+            }
+            EscrowError::Arbitrated => "Arbitrated escrows can only be settled by a ruling",
+            EscrowError::InvalidArbitration => {
+                "Arbiter, buyer and seller must differ, with no mint B, price, basket, expiry, taker or net fee mode"
+            }
+            EscrowError::InvalidRuling => "Signer may not make this ruling",
+            EscrowError::HasMilestones => "Milestone escrows can only be approved or refunded",
+            EscrowError::InvalidMilestones => {
+                "Milestones need short names and non-zero amounts adding up to the deposit, with no mint B, price, basket, expiry, taker or net fee mode"
+            }
+            EscrowError::TooManyMilestones => "Too many milestones",
+            EscrowError::MilestoneUnavailable => "Milestone does not exist or was already released",
+            EscrowError::InvalidProgramData => "Account is not this program's program data",
+            EscrowError::NotUpgradeAuthority => "Signer is not the program's upgrade authority",
+            EscrowError::NoTokenPrograms => "At least one token program must be allowed",
+            EscrowError::AuctionActive => {
+                "Mint B is priced by an auction; end it before setting a fixed price"
+            }
+        }
+    }
+}
+
+impl EscrowError {
+    /// Recovers the escrow error behind a `ProgramError::Custom` code.
+    pub fn decode(error: &ProgramError) -> Option<Self> {
+        match error {
+            ProgramError::Custom(code) => Self::try_from(*code).ok(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_pubkey::Pubkey;
+
+use crate::state::{Auction, Milestone};
+
+/// First field of every event log, so indexers can tell escrow events apart
+/// from other `Program data:` lines.
+pub const EVENT_TAG: &[u8; 8] = b"escrowev";
+
+/// Lifecycle events written to the transaction log with `sol_log_data`, as
+/// the two fields `[EVENT_TAG, borsh(event)]`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub enum EscrowEvent {
+    Created {
+        escrow: Pubkey,
+        maker: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        /// Mint A held by the vault after any transfer fee.
+        amount: u64,
+        receive_amount: u64,
+    },
+    Filled {
+        escrow: Pubkey,
+        maker: Pubkey,
+        taker: Pubkey,
+        mint_a: Pubkey,
+        /// The mint the taker paid in: mint B or one of the escrow's other
+        /// payment mints.
+        mint_b: Pubkey,
+        /// Amount of `mint_b` paid.
+        paid: u64,
+        /// Part of `paid` that went to the protocol treasury.
+        protocol_fee: u64,
+        /// Mint A sent to the taker.
+        released: u64,
+        /// `mint_b` still owed; zero once the escrow is closed.
+        remaining: u64,
+    },
+    Refunded {
+        escrow: Pubkey,
+        maker: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        amount: u64,
+    },
+    /// The maker changed the terms; fields carry the terms now in force.
+    Updated {
+        escrow: Pubkey,
+        maker: Pubkey,
+        mint_a: Pubkey,
+        mint_b: Pubkey,
+        amount: u64,
+        receive_amount: u64,
+        expires_at: Option<i64>,
+    },
+    /// A basket mint was bundled into the escrow as it was made.
+    BasketDeposited {
+        escrow: Pubkey,
+        maker: Pubkey,
+        mint: Pubkey,
+        /// Amount held by the mint's vault after any transfer fee.
+        amount: u64,
+    },
+    /// The maker started accepting another payment mint, re-priced it, or
+    /// stopped accepting it when `receive_amount` is zero.
+    PaymentMintSet {
+        escrow: Pubkey,
+        maker: Pubkey,
+        mint: Pubkey,
+        receive_amount: u64,
+    },
+    /// The maker started, changed or ended a Dutch auction.
+    AuctionSet {
+        escrow: Pubkey,
+        maker: Pubkey,
+        auction: Option<Auction>,
+    },
+    /// The maker made the escrow an arbitrated payment, as the buyer.
+    ArbiterSet {
+        escrow: Pubkey,
+        maker: Pubkey,
+        arbiter: Pubkey,
+        seller: Pubkey,
+    },
+    /// An arbitrated escrow was settled and closed.
+    Settled {
+        escrow: Pubkey,
+        maker: Pubkey,
+        /// The buyer, seller or arbiter who made the ruling.
+        ruled_by: Pubkey,
+        seller: Pubkey,
+        /// Mint A sent to the seller.
+        seller_amount: u64,
+        /// Mint A returned to the buyer.
+        buyer_amount: u64,
+    },
+    /// The maker split the deposit into milestones for a beneficiary.
+    MilestonesSet {
+        escrow: Pubkey,
+        maker: Pubkey,
+        beneficiary: Pubkey,
+        milestones: Vec<Milestone>,
+    },
+    /// The maker approved a milestone and it was paid to the beneficiary.
+    MilestoneReleased {
+        escrow: Pubkey,
+        maker: Pubkey,
+        beneficiary: Pubkey,
+        index: u8,
+        amount: u64,
+        /// Mint A still unreleased; zero once the escrow is closed.
+        remaining: u64,
+    },
+}
+
+impl EscrowEvent {
+    pub fn emit(&self) {
+        let data = borsh::to_vec(self).unwrap();
+        log_data(&[EVENT_TAG, &data]);
+    }
+
+    /// Decodes the raw fields of one `sol_log_data` call, returning `None` for
+    /// data that is not an escrow event.
+    pub fn from_log_data(fields: &[&[u8]]) -> Option<Self> {
+        match fields {
+            [tag, data] if tag == EVENT_TAG => Self::try_from_slice(data).ok(),
+            _ => None,
+        }
+    }
+
+    /// Decodes a `Program data: ...` line from transaction logs.
+    #[cfg(feature = "no-entrypoint")]
+    pub fn from_log(line: &str) -> Option<Self> {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let fields = line
+            .strip_prefix("Program data: ")?
+            .split(' ')
+            .map(|field| STANDARD.decode(field).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+        Self::from_log_data(&fields)
+    }
+}
+
+fn log_data(data: &[&[u8]]) {
+    #[cfg(target_os = "solana")]
+    unsafe {
+        solana_define_syscall::definitions::sol_log_data(
+            data as *const _ as *const u8,
+            data.len() as u64,
+        )
+    };
+
+    #[cfg(not(target_os = "solana"))]
+    solana_sysvar::program_stubs::sol_log_data(data);
+}
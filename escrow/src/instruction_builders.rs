@@ -0,0 +1,500 @@
+//! Client-side builders that derive the escrow PDAs and lay out accounts in
+//! the order the processor expects.
+//!
+//! For native SOL, pass `spl_token_interface::native_mint::ID` as the mint,
+//! the user's wallet as their token account and the system program as that
+//! side's token program.
+
+use solana_instruction::{AccountMeta, Instruction};
+use solana_loader_v3_interface::get_program_data_address;
+use solana_pubkey::Pubkey;
+use solana_system_interface::program as system_program;
+
+use crate::{
+    instructions::{EscrowInstructions, MakeArgs, UpdateArgs},
+    state::Auction,
+};
+
+pub fn find_escrow_address(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    seed: u64,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"escrow",
+            maker.as_ref(),
+            mint_a.as_ref(),
+            mint_b.as_ref(),
+            &seed.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+pub fn find_vault_address(program_id: &Pubkey, escrow: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", escrow.as_ref()], program_id)
+}
+
+pub fn find_basket_vault_address(
+    program_id: &Pubkey,
+    escrow: &Pubkey,
+    mint: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", escrow.as_ref(), mint.as_ref()], program_id)
+}
+
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], program_id)
+}
+
+/// Has `rent_payer` rather than the maker fund the escrow and vault rent of a
+/// [`make`] instruction, or the extra escrow rent of a [`set_payment_mint`]
+/// on an escrow it sponsored. The rent payer must sign the transaction too.
+pub fn sponsor_rent(mut make: Instruction, rent_payer: &Pubkey) -> Instruction {
+    make.accounts.push(AccountMeta::new(*rent_payer, true));
+    make
+}
+
+/// Appends the rent payer to a [`take`], [`refund`], [`close_expired`],
+/// [`settle`] or [`approve_milestone`] instruction so it gets its rent back;
+/// only needed when someone other than the maker sponsored the escrow.
+pub fn return_rent_to(mut ix: Instruction, rent_payer: &Pubkey) -> Instruction {
+    ix.accounts.push(AccountMeta::new(*rent_payer, false));
+    ix
+}
+
+/// One basket leg of an escrow. `token_account` is the maker's account in
+/// [`make`], [`refund`] and [`close_expired`], and the taker's in [`take`],
+/// where it is created if missing.
+pub struct BasketLegAccounts {
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+    pub token_program: Pubkey,
+}
+
+/// Appends the basket legs of `escrow`, in the order they were made, to a
+/// [`make`], [`take`], [`refund`] or [`close_expired`] instruction. Must come
+/// before [`sponsor_rent`] and [`return_rent_to`].
+pub fn with_basket(
+    program_id: &Pubkey,
+    mut ix: Instruction,
+    escrow: &Pubkey,
+    legs: &[BasketLegAccounts],
+) -> Instruction {
+    for leg in legs {
+        let (vault, _) = find_basket_vault_address(program_id, escrow, &leg.mint);
+        ix.accounts.extend([
+            vault_mint(&leg.mint, &leg.token_program),
+            AccountMeta::new(leg.token_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(leg.token_program, false),
+        ]);
+    }
+    ix
+}
+
+/// Accounts for [`take`], with the token program that owns each mint.
+///
+/// `treasury_token_b` receives the protocol fee: the treasury's mint B token
+/// account, or the treasury wallet when mint B is native SOL. While no fee is
+/// charged it is not checked, so any account will do.
+///
+/// `taker_token_a` and `maker_token_b` are created, at the taker's expense, if
+/// they do not exist yet; they must then be associated token accounts.
+pub struct TakeAccounts {
+    pub taker: Pubkey,
+    pub maker: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub taker_token_a: Pubkey,
+    pub taker_token_b: Pubkey,
+    pub maker_token_b: Pubkey,
+    pub treasury_token_b: Pubkey,
+    pub token_program_a: Pubkey,
+    pub token_program_b: Pubkey,
+}
+
+/// Builds `Make`. A basket needs its amounts in `args.basket` and its legs
+/// appended with [`with_basket`], in the same order.
+pub fn make(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    maker_token_a: &Pubkey,
+    token_program: &Pubkey,
+    args: MakeArgs,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, maker, mint_a, mint_b, args.seed);
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new_readonly(*mint_a, false),
+            AccountMeta::new_readonly(*mint_b, false),
+            AccountMeta::new(*maker_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::Make(args)).unwrap(),
+    }
+}
+
+pub fn take(program_id: &Pubkey, accounts: &TakeAccounts, seed: u64, amount: u64) -> Instruction {
+    take_paying_with(program_id, accounts, &accounts.mint_b, seed, amount)
+}
+
+/// Builds [`take`] paying in `payment_mint`, one of the escrow's other
+/// payment mints. `accounts.mint_b` is still the escrow's mint B, but
+/// `taker_token_b`, `maker_token_b`, `treasury_token_b` and `token_program_b`
+/// are for `payment_mint`.
+pub fn take_paying_with(
+    program_id: &Pubkey,
+    accounts: &TakeAccounts,
+    payment_mint: &Pubkey,
+    seed: u64,
+    amount: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(
+        program_id,
+        &accounts.maker,
+        &accounts.mint_a,
+        &accounts.mint_b,
+        seed,
+    );
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(accounts.taker, true),
+            AccountMeta::new(accounts.maker, false),
+            vault_mint(&accounts.mint_a, &accounts.token_program_a),
+            AccountMeta::new_readonly(*payment_mint, false),
+            AccountMeta::new(accounts.taker_token_a, false),
+            AccountMeta::new(accounts.taker_token_b, false),
+            AccountMeta::new(accounts.maker_token_b, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(accounts.token_program_a, false),
+            AccountMeta::new_readonly(accounts.token_program_b, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(accounts.treasury_token_b, false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::Take { amount }).unwrap(),
+    }
+}
+
+/// Accounts for [`update`]. `maker_token_a` funds a deposit or receives a
+/// withdrawal.
+pub struct UpdateAccounts {
+    pub maker: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub maker_token_a: Pubkey,
+    pub token_program: Pubkey,
+}
+
+pub fn update(
+    program_id: &Pubkey,
+    accounts: &UpdateAccounts,
+    seed: u64,
+    args: UpdateArgs,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(
+        program_id,
+        &accounts.maker,
+        &accounts.mint_a,
+        &accounts.mint_b,
+        seed,
+    );
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(accounts.maker, true),
+            AccountMeta::new_readonly(accounts.mint_a, false),
+            AccountMeta::new_readonly(accounts.mint_b, false),
+            AccountMeta::new(accounts.maker_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(accounts.token_program, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::Update(args)).unwrap(),
+    }
+}
+
+pub fn refund(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    maker_token_a: &Pubkey,
+    token_program: &Pubkey,
+    seed: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, maker, mint_a, mint_b, seed);
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            vault_mint(mint_a, token_program),
+            AccountMeta::new_readonly(*mint_b, false),
+            AccountMeta::new(*maker_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::Refund).unwrap(),
+    }
+}
+
+/// Builds `CloseExpired`, which anyone may send once the escrow has expired;
+/// the maker only receives the funds and does not sign.
+pub fn close_expired(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    maker_token_a: &Pubkey,
+    token_program: &Pubkey,
+    seed: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(program_id, maker, mint_a, mint_b, seed);
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*maker, false),
+            vault_mint(mint_a, token_program),
+            AccountMeta::new_readonly(*mint_b, false),
+            AccountMeta::new(*maker_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::CloseExpired).unwrap(),
+    }
+}
+
+/// Builds `SetPaymentMint`, accepting `mint` as payment for the escrow at
+/// `receive_amount`, or no longer accepting it when that is zero. When a new
+/// option grows the escrow account, the escrow's rent payer pays the extra
+/// rent; apply [`sponsor_rent`] if that is not the maker.
+pub fn set_payment_mint(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    escrow: &Pubkey,
+    mint: &Pubkey,
+    receive_amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*maker, true),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(*escrow, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::SetPaymentMint { receive_amount }).unwrap(),
+    }
+}
+
+/// Builds `SetAuction`, which starts or replaces a Dutch auction on mint B,
+/// or ends it when `auction` is `None`.
+pub fn set_auction(
+    program_id: &Pubkey,
+    maker: &Pubkey,
+    escrow: &Pubkey,
+    auction: Option<Auction>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*maker, true),
+            AccountMeta::new(*escrow, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::SetAuction { auction }).unwrap(),
+    }
+}
+
+/// Accounts for [`settle`]. `signer` is the arbiter, the buyer (the maker) or
+/// the seller. `seller_token_a` and `maker_token_a` receive their shares of
+/// mint A; an account whose share is zero is not checked.
+pub struct SettleAccounts {
+    pub signer: Pubkey,
+    pub maker: Pubkey,
+    pub mint_a: Pubkey,
+    pub seller_token_a: Pubkey,
+    pub maker_token_a: Pubkey,
+    pub token_program: Pubkey,
+}
+
+pub fn settle(
+    program_id: &Pubkey,
+    accounts: &SettleAccounts,
+    seed: u64,
+    seller_amount: u64,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(
+        program_id,
+        &accounts.maker,
+        &accounts.mint_a,
+        &Pubkey::default(),
+        seed,
+    );
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(accounts.signer, true),
+            AccountMeta::new(accounts.maker, false),
+            vault_mint(&accounts.mint_a, &accounts.token_program),
+            AccountMeta::new(accounts.seller_token_a, false),
+            AccountMeta::new(accounts.maker_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(accounts.token_program, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::Settle { seller_amount }).unwrap(),
+    }
+}
+
+/// Accounts for [`approve_milestone`]. `beneficiary_token_a` is the
+/// beneficiary's mint A token account, or their wallet for native SOL.
+pub struct ApproveMilestoneAccounts {
+    pub maker: Pubkey,
+    pub mint_a: Pubkey,
+    pub beneficiary_token_a: Pubkey,
+    pub token_program: Pubkey,
+}
+
+pub fn approve_milestone(
+    program_id: &Pubkey,
+    accounts: &ApproveMilestoneAccounts,
+    seed: u64,
+    index: u8,
+) -> Instruction {
+    let (escrow, _) = find_escrow_address(
+        program_id,
+        &accounts.maker,
+        &accounts.mint_a,
+        &Pubkey::default(),
+        seed,
+    );
+    let (vault, _) = find_vault_address(program_id, &escrow);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(accounts.maker, true),
+            vault_mint(&accounts.mint_a, &accounts.token_program),
+            AccountMeta::new(accounts.beneficiary_token_a, false),
+            AccountMeta::new(escrow, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(accounts.token_program, false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::ApproveMilestone { index }).unwrap(),
+    }
+}
+
+/// Builds `InitializeConfig`; the signer must be the program's upgrade
+/// authority and becomes the config admin.
+pub fn initialize_config(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    fee_bps: u16,
+    treasury: &Pubkey,
+    allowed_token_programs: Vec<Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(find_config_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(get_program_data_address(program_id), false),
+        ],
+        data: borsh::to_vec(&EscrowInstructions::InitializeConfig {
+            fee_bps,
+            treasury: *treasury,
+            allowed_token_programs,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn update_fee(program_id: &Pubkey, admin: &Pubkey, fee_bps: u16) -> Instruction {
+    admin_instruction(program_id, admin, EscrowInstructions::UpdateFee { fee_bps })
+}
+
+pub fn set_paused(program_id: &Pubkey, admin: &Pubkey, paused: bool) -> Instruction {
+    admin_instruction(program_id, admin, EscrowInstructions::SetPaused { paused })
+}
+
+pub fn transfer_admin(program_id: &Pubkey, admin: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    admin_instruction(
+        program_id,
+        admin,
+        EscrowInstructions::TransferAdmin {
+            new_admin: *new_admin,
+        },
+    )
+}
+
+pub fn set_allowed_token_programs(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    allowed_token_programs: Vec<Pubkey>,
+) -> Instruction {
+    admin_instruction(
+        program_id,
+        admin,
+        EscrowInstructions::SetAllowedTokenPrograms {
+            allowed_token_programs,
+        },
+    )
+}
+
+/// The mint of a vault the instruction may close. Token-2022 mints are
+/// writable, so transfer fees withheld in the vault can be harvested to them.
+fn vault_mint(mint: &Pubkey, token_program: &Pubkey) -> AccountMeta {
+    if *token_program == spl_token_2022_interface::ID {
+        AccountMeta::new(*mint, false)
+    } else {
+        AccountMeta::new_readonly(*mint, false)
+    }
+}
+
+fn admin_instruction(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    instruction: EscrowInstructions,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(find_config_address(program_id).0, false),
+        ],
+        data: borsh::to_vec(&instruction).unwrap(),
+    }
+}
@@ -1,13 +1,106 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_pubkey::Pubkey;
+
+use crate::state::{Auction, FeeMode};
 
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub enum EscrowInstructions {
-    Make {
-        amount_offered: u64,
-        amount_required: u64,
+pub struct MakeArgs {
+    pub seed: u64,
+    pub amount_offered: u64,
+    pub amount_required: u64,
+    pub expires_at: Option<i64>,
+    pub allowed_taker: Option<Pubkey>,
+    pub receive_fee_mode: FeeMode,
+    /// Amount of each basket mint to bundle with mint A, in the order their
+    /// accounts follow the make accounts.
+    pub basket: Vec<u64>,
+    pub kind: EscrowKind,
+}
+
+/// What an escrow's deposit is for.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub enum EscrowKind {
+    /// A swap for `amount_required` of mint B.
+    Swap,
+    /// A payment to `seller` that only a ruling by `arbiter`, or a concession
+    /// by either party, can settle. It has no mint B: pass `Pubkey::default()`
+    /// in its place and zero as `amount_required`.
+    Arbitrated { arbiter: Pubkey, seller: Pubkey },
+    /// A payment to `beneficiary` split into milestones the maker approves
+    /// one by one; their amounts must add up to the deposit. Like an
+    /// arbitrated payment, it has no mint B or price.
+    Milestones {
+        beneficiary: Pubkey,
+        milestones: Vec<MilestoneArgs>,
     },
+}
+
+/// New terms for an open escrow; `None` and zero leave a term unchanged.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct UpdateArgs {
+    /// Rejected while an auction sets mint B's price.
+    pub receive_amount: Option<u64>,
+    /// Mint A to add to the vault. A deposit or withdrawal rescales the
+    /// payment option and auction prices to keep their rate, while
+    /// `receive_amount` only changes when set.
+    pub deposit: u64,
+    /// Mint A to return to the maker; at least some must stay in the vault.
+    pub withdraw: u64,
+    /// `Some(None)` removes the expiry.
+    pub expires_at: Option<Option<i64>>,
+}
+
+/// One milestone of [`EscrowKind::Milestones`].
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct MilestoneArgs {
+    /// At most `Milestone::MAX_NAME_LEN` bytes.
+    pub name: String,
+    pub amount: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub enum EscrowInstructions {
+    Make(MakeArgs),
     Take {
         amount: u64,
     },
     Refund,
+    CloseExpired,
+    InitializeConfig {
+        fee_bps: u16,
+        treasury: Pubkey,
+        allowed_token_programs: Vec<Pubkey>,
+    },
+    UpdateFee {
+        fee_bps: u16,
+    },
+    SetPaused {
+        paused: bool,
+    },
+    TransferAdmin {
+        new_admin: Pubkey,
+    },
+    Update(UpdateArgs),
+    /// Accepts another mint as payment at `receive_amount`; zero stops
+    /// accepting it.
+    SetPaymentMint {
+        receive_amount: u64,
+    },
+    /// Starts, replaces or, with `None`, ends a Dutch auction on mint B.
+    SetAuction {
+        auction: Option<Auction>,
+    },
+    /// Rules on an arbitrated escrow: `seller_amount` of the deposit goes to
+    /// the seller and the rest back to the buyer.
+    Settle {
+        seller_amount: u64,
+    },
+    /// Releases milestone `index` to the beneficiary.
+    ApproveMilestone {
+        index: u8,
+    },
+    /// Replaces the token programs the config allows.
+    SetAllowedTokenPrograms {
+        allowed_token_programs: Vec<Pubkey>,
+    },
 }
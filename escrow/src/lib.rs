@@ -1,13 +1,19 @@
 pub mod error;
+pub mod events;
+#[cfg(feature = "no-entrypoint")]
+pub mod instruction_builders;
 pub mod instructions;
 pub mod processor;
 pub mod state;
 
 use solana_account_info::AccountInfo;
-use solana_program_entrypoint::{entrypoint, ProgramResult};
+use solana_program_entrypoint::ProgramResult;
 use solana_pubkey::Pubkey;
 
-entrypoint!(process_instruction);
+solana_pubkey::declare_id!("DinjxyZz2tjZTVi5FbKNoi2aayH71Q3EMzEh8yiGJnVY");
+
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program_entrypoint::entrypoint!(process_instruction);
 
 pub fn process_instruction(
     program_id: &Pubkey,
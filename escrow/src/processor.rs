@@ -4,20 +4,33 @@ use solana_cpi::{invoke, invoke_signed};
 use solana_system_interface::instruction as system_instruction;
 
 use solana_account_info::{next_account_info, AccountInfo};
+use solana_loader_v3_interface::{get_program_data_address, state::UpgradeableLoaderState};
 use solana_program_entrypoint::ProgramResult;
 use solana_program_error::ProgramError;
-use solana_program_pack::Pack;
 use solana_pubkey::Pubkey;
 use solana_system_interface::program as system_program;
-use solana_sysvar::{rent::Rent, SysvarSerialize};
+use solana_sysvar::{clock::Clock, rent::Rent, Sysvar, SysvarSerialize};
 
-use spl_token_interface::{
+use spl_associated_token_account_interface::instruction::create_associated_token_account_idempotent;
+use spl_token_2022_interface::{
+    extension::{
+        transfer_fee::{self, TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
     instruction,
     state::{Account as TokenAccount, Mint},
-    ID as TOKEN_PROGRAM_ID,
 };
+use spl_token_interface::native_mint;
 
-use crate::{instructions::EscrowInstructions, state::Escrow};
+use crate::{
+    error::EscrowError,
+    events::EscrowEvent,
+    instructions::{EscrowInstructions, EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{
+        AccountType, Arbitration, Auction, BasketLeg, Config, Escrow, FeeMode, Milestone,
+        NativeSide, PaymentOption,
+    },
+};
 
 pub fn process(
     program_id: &Pubkey,
@@ -25,26 +38,67 @@ pub fn process(
     instruction_data: &[u8],
 ) -> ProgramResult {
     let instruction = EscrowInstructions::try_from_slice(instruction_data)
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+        .map_err(|_| EscrowError::InvalidInstruction)?;
 
     match instruction {
-        EscrowInstructions::Make {
-            amount_offered,
-            amount_required,
-        } => make(program_id, accounts, amount_offered, amount_required),
+        EscrowInstructions::Make(args) => make(program_id, accounts, args),
         EscrowInstructions::Take { amount } => take(program_id, accounts, amount),
         EscrowInstructions::Refund => refund(program_id, accounts),
+        EscrowInstructions::CloseExpired => close_expired(program_id, accounts),
+        EscrowInstructions::InitializeConfig {
+            fee_bps,
+            treasury,
+            allowed_token_programs,
+        } => initialize_config(
+            program_id,
+            accounts,
+            fee_bps,
+            treasury,
+            allowed_token_programs,
+        ),
+        EscrowInstructions::UpdateFee { fee_bps } => update_fee(program_id, accounts, fee_bps),
+        EscrowInstructions::SetPaused { paused } => set_paused(program_id, accounts, paused),
+        EscrowInstructions::TransferAdmin { new_admin } => {
+            transfer_admin(program_id, accounts, new_admin)
+        }
+        EscrowInstructions::Update(args) => update(program_id, accounts, args),
+        EscrowInstructions::SetPaymentMint { receive_amount } => {
+            set_payment_mint(program_id, accounts, receive_amount)
+        }
+        EscrowInstructions::SetAuction { auction } => set_auction(program_id, accounts, auction),
+        EscrowInstructions::Settle { seller_amount } => settle(program_id, accounts, seller_amount),
+        EscrowInstructions::ApproveMilestone { index } => {
+            approve_milestone(program_id, accounts, index)
+        }
+        EscrowInstructions::SetAllowedTokenPrograms {
+            allowed_token_programs,
+        } => set_allowed_token_programs(program_id, accounts, allowed_token_programs),
     }
 }
 
-pub fn make(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    amount_offered: u64,
-    amount_required: u64,
-) -> ProgramResult {
-    if amount_offered == 0 || amount_required == 0 {
-        return Err(ProgramError::InvalidArgument);
+pub fn make(program_id: &Pubkey, accounts: &[AccountInfo], args: MakeArgs) -> ProgramResult {
+    let MakeArgs {
+        seed,
+        amount_offered,
+        amount_required,
+        expires_at,
+        allowed_taker,
+        receive_fee_mode,
+        basket,
+        kind,
+    } = args;
+
+    if amount_offered == 0 || basket.contains(&0) {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    if basket.len() > Escrow::MAX_BASKET_LEGS {
+        return Err(EscrowError::BasketFull.into());
+    }
+
+    if let Some(expires_at) = expires_at {
+        if expires_at <= Clock::get()?.unix_timestamp {
+            return Err(EscrowError::InvalidExpiry.into());
+        }
     }
 
     let accs = &mut accounts.iter();
@@ -58,36 +112,121 @@ pub fn make(
     let token_program = next_account_info(accs)?;
     let system_program = next_account_info(accs)?;
     let rent_sysvar = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+    // Each basket leg passes its mint, the maker's token account, its vault
+    // and its token program.
+    let basket_accounts = basket
+        .iter()
+        .map(|_| {
+            Ok(BasketAccounts {
+                mint: next_account_info(accs)?,
+                token_account: next_account_info(accs)?,
+                vault: next_account_info(accs)?,
+                token_program: next_account_info(accs)?,
+            })
+        })
+        .collect::<Result<Vec<_>, ProgramError>>()?;
+    // A relayer may sponsor the rent by signing as an extra trailing account.
+    let rent_payer = accs.next().unwrap_or(maker);
 
-    if !maker.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    if !maker.is_signer || !rent_payer.is_signer {
+        return Err(EscrowError::MissingSignature.into());
     }
 
     if system_program.key != &system_program::id() {
-        return Err(ProgramError::IncorrectProgramId);
+        return Err(EscrowError::InvalidSystemProgram.into());
     }
 
-    if token_program.key != &TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
-    }
+    check_asset_program(token_program, mint_a)?;
 
-    if mint_a.key == mint_b.key {
-        return Err(ProgramError::InvalidArgument);
-    }
+    // Payments have no mint B or price, so they can never be taken as swaps,
+    // nor carry the swap terms: expiry, allowed taker and fee mode. A
+    // settlement or the last approval only pays out mint A, so basket legs
+    // would be stranded.
+    let is_payment = *mint_b.key == Pubkey::default()
+        && amount_required == 0
+        && basket_accounts.is_empty()
+        && expires_at.is_none()
+        && allowed_taker.is_none()
+        && receive_fee_mode == FeeMode::Gross;
+    let (arbitration, beneficiary, milestones) = match kind {
+        EscrowKind::Swap => {
+            if amount_required == 0 {
+                return Err(EscrowError::InvalidAmount.into());
+            }
+            (None, None, Vec::new())
+        }
+        EscrowKind::Arbitrated { arbiter, seller } => {
+            if !is_payment || arbiter == *maker.key || seller == *maker.key || arbiter == seller {
+                return Err(EscrowError::InvalidArbitration.into());
+            }
+            (Some(Arbitration { arbiter, seller }), None, Vec::new())
+        }
+        EscrowKind::Milestones {
+            beneficiary,
+            milestones,
+        } => {
+            if !is_payment {
+                return Err(EscrowError::InvalidMilestones.into());
+            }
+            (None, Some(beneficiary), check_milestones(milestones)?)
+        }
+    };
 
-    if maker_token_a.owner != token_program.key {
-        return Err(ProgramError::InvalidAccountOwner);
-    }
+    // Mint B's program is only passed to `take`, so its owner stands in here.
+    let mint_b_program = if is_native(mint_b) || arbitration.is_some() || beneficiary.is_some() {
+        &system_program::ID
+    } else {
+        mint_b.owner
+    };
+    let mut token_programs = vec![token_program.key, mint_b_program];
+    token_programs.extend(basket_accounts.iter().map(|leg| leg.token_program.key));
+    let config = load_config(program_id, config_account)?;
+    check_config_allows(config.as_ref(), &token_programs)?;
 
-    let maker_token_a_account = TokenAccount::unpack(&maker_token_a.data.borrow())?;
-    if maker_token_a_account.owner != *maker.key {
-        return Err(ProgramError::IllegalOwner);
+    if mint_a.key == mint_b.key {
+        return Err(EscrowError::SameMint.into());
     }
-    if maker_token_a_account.mint != *mint_a.key {
-        return Err(ProgramError::InvalidAccountData);
+    for (i, leg) in basket_accounts.iter().enumerate() {
+        if is_native(leg.mint)
+            || leg.mint.key == mint_a.key
+            || leg.mint.key == mint_b.key
+            || basket_accounts[..i]
+                .iter()
+                .any(|other| other.mint.key == leg.mint.key)
+        {
+            return Err(EscrowError::InvalidBasketMint.into());
+        }
     }
-    if maker_token_a_account.amount < amount_offered {
-        return Err(ProgramError::InsufficientFunds);
+
+    let native_side = if is_native(mint_a) {
+        NativeSide::MintA
+    } else if is_native(mint_b) {
+        NativeSide::MintB
+    } else {
+        NativeSide::None
+    };
+
+    if is_native(mint_a) {
+        // Native SOL is deposited straight from the maker's wallet.
+        if maker_token_a.key != maker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+    } else {
+        if maker_token_a.owner != token_program.key {
+            return Err(EscrowError::InvalidTokenAccount.into());
+        }
+
+        let maker_token_a_account = unpack_token_account(maker_token_a)?;
+        if maker_token_a_account.owner != *maker.key {
+            return Err(EscrowError::InvalidUser.into());
+        }
+        if maker_token_a_account.mint != *mint_a.key {
+            return Err(EscrowError::InvalidMint.into());
+        }
+        if maker_token_a_account.amount < amount_offered {
+            return Err(EscrowError::InsufficientFunds.into());
+        }
     }
 
     let (escrow_pda, escrow_bump) = Pubkey::find_program_address(
@@ -96,111 +235,205 @@ pub fn make(
             maker.key.as_ref(),
             mint_a.key.as_ref(),
             mint_b.key.as_ref(),
+            &seed.to_le_bytes(),
         ],
         program_id,
     );
 
     if escrow_pda != *escrow_state.key {
-        return Err(ProgramError::InvalidSeeds);
+        return Err(EscrowError::InvalidEscrowAddress.into());
     }
 
     let (vault_pda, vault_bump) =
         Pubkey::find_program_address(&[b"vault", escrow_state.key.as_ref()], program_id);
 
     if vault_pda != *escrow_vault.key {
-        return Err(ProgramError::InvalidSeeds);
+        return Err(EscrowError::InvalidVault.into());
     }
 
     if escrow_state.lamports() != 0 || escrow_vault.lamports() != 0 {
-        return Err(ProgramError::AccountAlreadyInitialized);
+        return Err(EscrowError::EscrowAlreadyExists.into());
     }
 
     let rent = Rent::from_account_info(rent_sysvar)?;
 
+    let deposited = if is_native(mint_a) {
+        // Native SOL is held as lamports in a program-owned vault with no data.
+        invoke_signed(
+            &system_instruction::create_account(
+                rent_payer.key,
+                escrow_vault.key,
+                rent.minimum_balance(0),
+                0,
+                program_id,
+            ),
+            &[
+                rent_payer.clone(),
+                escrow_vault.clone(),
+                system_program.clone(),
+            ],
+            &[&[b"vault", escrow_state.key.as_ref(), &[vault_bump]]],
+        )?;
+        invoke(
+            &system_instruction::transfer(maker.key, escrow_vault.key, amount_offered),
+            &[maker.clone(), escrow_vault.clone(), system_program.clone()],
+        )?;
+
+        amount_offered
+    } else {
+        create_token_vault(
+            rent_payer,
+            escrow_vault,
+            mint_a,
+            escrow_state,
+            token_program,
+            system_program,
+            &[b"vault", escrow_state.key.as_ref(), &[vault_bump]],
+        )?;
+
+        invoke(
+            &instruction::transfer_checked(
+                token_program.key,
+                maker_token_a.key,
+                mint_a.key,
+                escrow_vault.key,
+                maker.key,
+                &[maker.key],
+                amount_offered,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                maker_token_a.clone(),
+                mint_a.clone(),
+                escrow_vault.clone(),
+                maker.clone(),
+                token_program.clone(),
+            ],
+        )?;
+
+        // Record what actually landed in the vault, which is less than
+        // `amount_offered` when mint A charges a transfer fee.
+        unpack_token_account(escrow_vault)?.amount
+    };
+    if deposited == 0 {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    // Milestones split what actually landed in the vault.
+    if beneficiary.is_some() && milestones.iter().map(|m| m.amount).sum::<u64>() != deposited {
+        return Err(EscrowError::InvalidMilestones.into());
+    }
+
+    // The basket is deposited along with mint A, so the escrow can never be
+    // taken without it.
+    let basket = basket_accounts
+        .iter()
+        .zip(basket)
+        .map(|(leg, amount)| {
+            deposit_basket_leg(
+                program_id,
+                leg,
+                amount,
+                maker,
+                rent_payer,
+                escrow_state,
+                system_program,
+            )
+        })
+        .collect::<Result<Vec<_>, ProgramError>>()?;
+
+    let escrow = Escrow {
+        account_type: AccountType::Escrow,
+        version: Escrow::VERSION,
+        owner: *maker.key,
+        rent_payer: *rent_payer.key,
+        seed,
+        mint_a: *mint_a.key,
+        mint_b: *mint_b.key,
+        amount: deposited,
+        receive_amount: amount_required,
+        expires_at,
+        allowed_taker,
+        receive_fee_mode,
+        native_side,
+        bump: escrow_bump,
+        vault_bump,
+        basket,
+        payment_options: Vec::new(),
+        auction: None,
+        arbitration,
+        beneficiary,
+        milestones,
+    };
+
+    // Sized for the sections this escrow uses rather than the worst case.
+    let space = escrow.space();
     invoke_signed(
         &system_instruction::create_account(
-            maker.key,
+            rent_payer.key,
             escrow_state.key,
-            rent.minimum_balance(Escrow::LEN),
-            Escrow::LEN as u64,
+            rent.minimum_balance(space),
+            space as u64,
             program_id,
         ),
-        &[maker.clone(), escrow_state.clone(), system_program.clone()],
+        &[
+            rent_payer.clone(),
+            escrow_state.clone(),
+            system_program.clone(),
+        ],
         &[&[
             b"escrow",
             maker.key.as_ref(),
             mint_a.key.as_ref(),
             mint_b.key.as_ref(),
+            &seed.to_le_bytes(),
             &[escrow_bump],
         ]],
     )?;
+    escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
 
-    invoke_signed(
-        &system_instruction::create_account(
-            maker.key,
-            escrow_vault.key,
-            rent.minimum_balance(TokenAccount::LEN),
-            TokenAccount::LEN as u64,
-            token_program.key,
-        ),
-        &[maker.clone(), escrow_vault.clone(), system_program.clone()],
-        &[&[b"vault", escrow_state.key.as_ref(), &[vault_bump]]],
-    )?;
-
-    invoke(
-        &instruction::initialize_account3(
-            token_program.key,
-            escrow_vault.key,
-            mint_a.key,
-            &escrow_pda,
-        )?,
-        &[
-            escrow_vault.clone(),
-            mint_a.clone(),
-            escrow_state.clone(),
-            token_program.clone(),
-        ],
-    )?;
-
-    let mint_info = Mint::unpack(&mint_a.data.borrow())?;
-
-    invoke(
-        &instruction::transfer_checked(
-            token_program.key,
-            maker_token_a.key,
-            mint_a.key,
-            escrow_vault.key,
-            maker.key,
-            &[maker.key],
-            amount_offered,
-            mint_info.decimals,
-        )?,
-        &[
-            maker_token_a.clone(),
-            mint_a.clone(),
-            escrow_vault.clone(),
-            maker.clone(),
-            token_program.clone(),
-        ],
-    )?;
-
-    let escrow = Escrow {
-        owner: *maker.key,
+    EscrowEvent::Created {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
         mint_a: *mint_a.key,
         mint_b: *mint_b.key,
-        amount: amount_offered,
+        amount: deposited,
         receive_amount: amount_required,
-        bump: escrow_bump,
-        vault_bump,
-    };
+    }
+    .emit();
+    for leg in &escrow.basket {
+        EscrowEvent::BasketDeposited {
+            escrow: *escrow_state.key,
+            maker: *maker.key,
+            mint: leg.mint,
+            amount: leg.amount,
+        }
+        .emit();
+    }
+    if let Some(Arbitration { arbiter, seller }) = escrow.arbitration {
+        EscrowEvent::ArbiterSet {
+            escrow: *escrow_state.key,
+            maker: *maker.key,
+            arbiter,
+            seller,
+        }
+        .emit();
+    }
+    if let Some(beneficiary) = escrow.beneficiary {
+        EscrowEvent::MilestonesSet {
+            escrow: *escrow_state.key,
+            maker: *maker.key,
+            beneficiary,
+            milestones: escrow.milestones,
+        }
+        .emit();
+    }
 
-    escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
     Ok(())
 }
 
 pub fn take(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
     if amount == 0 {
-        return Err(ProgramError::InvalidArgument);
+        return Err(EscrowError::InvalidAmount.into());
     }
 
     let accs = &mut accounts.iter();
@@ -215,107 +448,344 @@ pub fn take(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> Progr
     let escrow_state = next_account_info(accs)?;
     let escrow_vault = next_account_info(accs)?;
     let system_program = next_account_info(accs)?;
-    let token_program = next_account_info(accs)?;
+    let token_program_a = next_account_info(accs)?;
+    let token_program_b = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+    let treasury_token_b = next_account_info(accs)?;
+    let associated_token_program = next_account_info(accs)?;
 
     if !taker.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+        return Err(EscrowError::MissingSignature.into());
     }
     if system_program.key != &system_program::id() {
-        return Err(ProgramError::IncorrectProgramId);
+        return Err(EscrowError::InvalidSystemProgram.into());
     }
-    if token_program.key != &TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
+    if associated_token_program.key != &spl_associated_token_account_interface::program::ID {
+        return Err(EscrowError::InvalidAssociatedTokenProgram.into());
     }
+    check_asset_program(token_program_a, mint_a)?;
+    check_asset_program(token_program_b, mint_b)?;
 
-    let escrow = Escrow::try_from_slice(&escrow_state.data.borrow())?;
-    if amount != escrow.receive_amount {
-        return Err(ProgramError::InvalidArgument);
-    }
+    let config = load_config(program_id, config_account)?;
+    check_config_allows(config.as_ref(), &[token_program_a.key, token_program_b.key])?;
 
-    let (escrow_pda, escrow_bump) = Pubkey::find_program_address(
-        &[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-        ],
+    let mut escrow = load_escrow(program_id, escrow_state)?;
+    check_swappable(&escrow)?;
+    let basket = next_basket_accounts(accs, program_id, &escrow, escrow_state)?;
+    let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+    // `mint_b` is whichever accepted mint the taker pays in, so the escrow is
+    // checked against its own mint B.
+    let escrow_mint_b = escrow.mint_b;
+    let (escrow_pda, escrow_bump) = validate_escrow_accounts(
         program_id,
-    );
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        &escrow_mint_b,
+    )?;
+    // While a Dutch auction runs, `amount` caps what the taker pays in mint B:
+    // anything at or above the current price buys the rest at that price.
+    let (receive_amount, amount) = match escrow.auction.filter(|_| *mint_b.key == escrow_mint_b) {
+        Some(auction) => {
+            let price = auction.price_at(Clock::get()?.unix_timestamp);
+            (price, amount.min(price))
+        }
+        None => (
+            escrow
+                .receive_amount_in(mint_b.key)
+                .ok_or(EscrowError::MintMismatch)?,
+            amount,
+        ),
+    };
+    let basket_programs: Vec<&Pubkey> = basket.iter().map(|leg| leg.token_program.key).collect();
+    check_config_allows(config.as_ref(), &basket_programs)?;
+    if amount > receive_amount {
+        return Err(EscrowError::AmountExceedsRemaining.into());
+    }
+    if !escrow.basket.is_empty() && amount != receive_amount {
+        return Err(EscrowError::PartialFillNotAllowed.into());
+    }
+    if escrow.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(EscrowError::EscrowExpired.into());
+    }
+    if escrow
+        .allowed_taker
+        .is_some_and(|allowed_taker| allowed_taker != *taker.key)
+    {
+        return Err(EscrowError::TakerNotAllowed.into());
+    }
+    let seed = escrow.seed;
 
-    let mint_a_info = Mint::unpack(&mint_a.data.borrow())?;
-    let mint_b_info = Mint::unpack(&mint_b.data.borrow())?;
+    // Release mint A pro-rata to the share of `receive_amount` being paid. The
+    // final fill takes whatever is left in the vault so rounding dust is never
+    // stranded.
+    let fully_filled = amount == receive_amount;
+    let release_amount = if fully_filled {
+        escrow.amount
+    } else {
+        u64::try_from(
+            (escrow.amount as u128)
+                .checked_mul(amount as u128)
+                .ok_or(EscrowError::Overflow)?
+                / receive_amount as u128,
+        )
+        .map_err(|_| EscrowError::Overflow)?
+    };
+    if release_amount == 0 {
+        return Err(EscrowError::FillTooSmall.into());
+    }
 
-    invoke(
-        &instruction::transfer_checked(
-            token_program.key,
-            taker_token_b.key,
-            mint_b.key,
-            maker_token_b.key,
-            taker.key,
-            &[taker.key],
-            escrow.receive_amount,
-            mint_b_info.decimals,
-        )?,
-        &[
-            taker_token_b.clone(),
-            mint_b.clone(),
-            maker_token_b.clone(),
-            taker.clone(),
-            token_program.clone(),
-        ],
-    )?;
+    // Open any missing token accounts at the taker's expense, so a fill never
+    // fails because the maker has not yet held mint B.
+    if escrow.native_side != NativeSide::MintA {
+        create_token_account_if_missing(
+            taker,
+            taker_token_a,
+            taker,
+            mint_a,
+            system_program,
+            token_program_a,
+            associated_token_program,
+        )?;
+    }
+    if !is_native(mint_b) {
+        create_token_account_if_missing(
+            taker,
+            maker_token_b,
+            maker,
+            mint_b,
+            system_program,
+            token_program_b,
+            associated_token_program,
+        )?;
+    }
+    for leg in &basket {
+        create_token_account_if_missing(
+            taker,
+            leg.token_account,
+            taker,
+            leg.mint,
+            system_program,
+            leg.token_program,
+            associated_token_program,
+        )?;
+    }
 
-    invoke_signed(
-        &instruction::transfer_checked(
-            token_program.key,
-            escrow_vault.key,
-            mint_a.key,
-            taker_token_a.key,
-            &escrow_pda,
-            &[],
-            escrow.amount,
-            mint_a_info.decimals,
-        )?,
-        &[
-            escrow_vault.clone(),
-            mint_a.clone(),
-            taker_token_a.clone(),
-            escrow_state.clone(),
-            token_program.clone(),
-        ],
-        &[&[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-            &[escrow_bump],
-        ]],
-    )?;
+    // The protocol fee is carved out of the mint B payment, so the taker still
+    // pays `amount` and the maker receives the rest.
+    let protocol_fee = config.as_ref().map_or(0, |config| config.fee_for(amount));
+    let maker_amount = amount - protocol_fee;
 
-    invoke_signed(
-        &instruction::close_account(
-            token_program.key,
-            escrow_vault.key,
-            maker.key,
-            &escrow_pda,
-            &[],
-        )?,
-        &[
-            escrow_vault.clone(),
-            maker.clone(),
-            escrow_state.clone(),
-            token_program.clone(),
-        ],
-        &[&[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-            &[escrow_bump],
-        ]],
-    )?;
+    if is_native(mint_b) {
+        if taker_token_b.key != taker.key || maker_token_b.key != maker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+
+        invoke(
+            &system_instruction::transfer(taker.key, maker.key, maker_amount),
+            &[taker.clone(), maker.clone(), system_program.clone()],
+        )?;
+
+        if let Some(config) = config.as_ref().filter(|_| protocol_fee > 0) {
+            if *treasury_token_b.key != config.treasury {
+                return Err(EscrowError::InvalidTreasury.into());
+            }
+            invoke(
+                &system_instruction::transfer(taker.key, treasury_token_b.key, protocol_fee),
+                &[
+                    taker.clone(),
+                    treasury_token_b.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+    } else {
+        if maker_token_b.owner != token_program_b.key {
+            return Err(EscrowError::InvalidTokenAccount.into());
+        }
+        let maker_account = unpack_token_account(maker_token_b)?;
+        if maker_account.owner != escrow.owner {
+            return Err(EscrowError::InvalidUser.into());
+        }
+        if maker_account.mint != *mint_b.key {
+            return Err(EscrowError::InvalidMint.into());
+        }
+
+        // In net mode the maker must end up with exactly their share of
+        // `amount`, so the taker also covers any transfer fee charged by the
+        // mint.
+        let pay_amount = match escrow.receive_fee_mode {
+            FeeMode::Gross => maker_amount,
+            FeeMode::Net => pre_fee_amount(mint_b, maker_amount)?,
+        };
+
+        invoke(
+            &instruction::transfer_checked(
+                token_program_b.key,
+                taker_token_b.key,
+                mint_b.key,
+                maker_token_b.key,
+                taker.key,
+                &[taker.key],
+                pay_amount,
+                mint_decimals(mint_b)?,
+            )?,
+            &[
+                taker_token_b.clone(),
+                mint_b.clone(),
+                maker_token_b.clone(),
+                taker.clone(),
+                token_program_b.clone(),
+            ],
+        )?;
+
+        if let Some(config) = config.as_ref().filter(|_| protocol_fee > 0) {
+            if treasury_token_b.owner != token_program_b.key {
+                return Err(EscrowError::InvalidTreasury.into());
+            }
+            let treasury_account = unpack_token_account(treasury_token_b)?;
+            if treasury_account.owner != config.treasury || treasury_account.mint != *mint_b.key {
+                return Err(EscrowError::InvalidTreasury.into());
+            }
+
+            invoke(
+                &instruction::transfer_checked(
+                    token_program_b.key,
+                    taker_token_b.key,
+                    mint_b.key,
+                    treasury_token_b.key,
+                    taker.key,
+                    &[taker.key],
+                    protocol_fee,
+                    mint_decimals(mint_b)?,
+                )?,
+                &[
+                    taker_token_b.clone(),
+                    mint_b.clone(),
+                    treasury_token_b.clone(),
+                    taker.clone(),
+                    token_program_b.clone(),
+                ],
+            )?;
+        }
+    }
+
+    if escrow.native_side == NativeSide::MintA {
+        if taker_token_a.key != taker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+        transfer_lamports(escrow_vault, taker_token_a, release_amount)?;
+    } else {
+        invoke_signed(
+            &instruction::transfer_checked(
+                token_program_a.key,
+                escrow_vault.key,
+                mint_a.key,
+                taker_token_a.key,
+                &escrow_pda,
+                &[],
+                release_amount,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                escrow_vault.clone(),
+                mint_a.clone(),
+                taker_token_a.clone(),
+                escrow_state.clone(),
+                token_program_a.clone(),
+            ],
+            &[&[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                escrow_mint_b.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ]],
+        )?;
+    }
 
-    **maker.lamports.borrow_mut() += escrow_state.lamports();
+    EscrowEvent::Filled {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        taker: *taker.key,
+        mint_a: *mint_a.key,
+        mint_b: *mint_b.key,
+        paid: amount,
+        protocol_fee,
+        released: release_amount,
+        remaining: receive_amount - amount,
+    }
+    .emit();
+
+    if !fully_filled {
+        // The paid price drops by what was paid; the other prices shrink with
+        // the deposit so every payment mint keeps its rate.
+        let remaining = escrow.amount - release_amount;
+        escrow.receive_amount = if escrow.mint_b == *mint_b.key && escrow.auction.is_none() {
+            escrow.receive_amount - amount
+        } else {
+            scale_price(escrow.receive_amount, remaining, escrow.amount)?
+        };
+        if let Some(auction) = &mut escrow.auction {
+            auction.start_price = scale_price(auction.start_price, remaining, escrow.amount)?;
+            auction.end_price = scale_price(auction.end_price, remaining, escrow.amount)?;
+        }
+        for option in &mut escrow.payment_options {
+            option.receive_amount = if option.mint == *mint_b.key {
+                option.receive_amount - amount
+            } else {
+                scale_price(option.receive_amount, remaining, escrow.amount)?
+            };
+        }
+        escrow.amount = remaining;
+        escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
+        return Ok(());
+    }
+
+    let seed_bytes = seed.to_le_bytes();
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        maker.key.as_ref(),
+        mint_a.key.as_ref(),
+        escrow_mint_b.as_ref(),
+        &seed_bytes,
+        &[escrow_bump],
+    ];
+    for (leg, leg_accounts) in escrow.basket.iter().zip(&basket) {
+        release_basket_leg(
+            leg_accounts,
+            leg.amount,
+            taker.key,
+            escrow_state,
+            rent_payer,
+            escrow_seeds,
+        )?;
+    }
+
+    if escrow.native_side == NativeSide::MintA {
+        transfer_lamports(escrow_vault, rent_payer, escrow_vault.lamports())?;
+    } else {
+        close_token_vault(
+            token_program_a,
+            escrow_vault,
+            mint_a,
+            rent_payer,
+            escrow_state,
+            &[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                escrow_mint_b.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ],
+        )?;
+    }
+
+    **rent_payer.lamports.borrow_mut() += escrow_state.lamports();
     **escrow_state.lamports.borrow_mut() = 0;
     escrow_state.data.borrow_mut().fill(0);
 
@@ -334,79 +804,1566 @@ pub fn refund(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let token_program = next_account_info(accs)?;
 
     if !maker.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+        return Err(EscrowError::MissingSignature.into());
+    }
+    check_asset_program(token_program, mint_a)?;
+
+    let escrow = load_escrow(program_id, escrow_state)?;
+    check_not_arbitrated(&escrow)?;
+    let basket = next_basket_accounts(accs, program_id, &escrow, escrow_state)?;
+    let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+    let seed = escrow.seed;
+    let (escrow_pda, escrow_bump) = validate_escrow_accounts(
+        program_id,
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        mint_b.key,
+    )?;
+
+    if escrow.native_side == NativeSide::MintA {
+        if maker_token_a.key != maker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+        transfer_lamports(escrow_vault, maker, escrow.amount)?;
+        transfer_lamports(escrow_vault, rent_payer, escrow_vault.lamports())?;
+    } else {
+        invoke_signed(
+            &instruction::transfer_checked(
+                token_program.key,
+                escrow_vault.key,
+                mint_a.key,
+                maker_token_a.key,
+                &escrow_pda,
+                &[],
+                escrow.amount,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                escrow_vault.clone(),
+                mint_a.clone(),
+                maker_token_a.clone(),
+                escrow_state.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                mint_b.key.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ]],
+        )?;
+
+        close_token_vault(
+            token_program,
+            escrow_vault,
+            mint_a,
+            rent_payer,
+            escrow_state,
+            &[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                mint_b.key.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ],
+        )?;
     }
-    if token_program.key != &TOKEN_PROGRAM_ID {
-        return Err(ProgramError::IncorrectProgramId);
+
+    let seed_bytes = seed.to_le_bytes();
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        maker.key.as_ref(),
+        mint_a.key.as_ref(),
+        mint_b.key.as_ref(),
+        &seed_bytes,
+        &[escrow_bump],
+    ];
+    for (leg, leg_accounts) in escrow.basket.iter().zip(&basket) {
+        release_basket_leg(
+            leg_accounts,
+            leg.amount,
+            maker.key,
+            escrow_state,
+            rent_payer,
+            escrow_seeds,
+        )?;
     }
 
-    let escrow = Escrow::try_from_slice(&escrow_state.data.borrow())?;
+    **rent_payer.try_borrow_mut_lamports()? += escrow_state.lamports();
+    **escrow_state.try_borrow_mut_lamports()? = 0;
+    escrow_state.data.borrow_mut().fill(0);
 
-    let (escrow_pda, escrow_bump) = Pubkey::find_program_address(
-        &[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-        ],
-        program_id,
-    );
+    EscrowEvent::Refunded {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        mint_a: *mint_a.key,
+        mint_b: *mint_b.key,
+        amount: escrow.amount,
+    }
+    .emit();
 
-    let mint_info = Mint::unpack(&mint_a.data.borrow())?;
+    Ok(())
+}
 
-    invoke_signed(
-        &instruction::transfer_checked(
-            token_program.key,
-            escrow_vault.key,
-            mint_a.key,
-            maker_token_a.key,
-            &escrow_pda,
-            &[],
-            escrow.amount,
-            mint_info.decimals,
-        )?,
-        &[
-            escrow_vault.clone(),
-            mint_a.clone(),
-            maker_token_a.clone(),
-            escrow_state.clone(),
-            token_program.clone(),
-        ],
-        &[&[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-            &[escrow_bump],
-        ]],
+pub fn close_expired(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let maker = next_account_info(accs)?;
+    let mint_a = next_account_info(accs)?;
+    let mint_b = next_account_info(accs)?;
+    let maker_token_a = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+    let escrow_vault = next_account_info(accs)?;
+    let token_program = next_account_info(accs)?;
+
+    // Anyone may crank an expired escrow, so every account is checked against
+    // the stored state instead of trusting a signature from the maker.
+    check_asset_program(token_program, mint_a)?;
+
+    let escrow = load_escrow(program_id, escrow_state)?;
+    check_not_arbitrated(&escrow)?;
+    let basket = next_basket_accounts(accs, program_id, &escrow, escrow_state)?;
+    let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+    let seed = escrow.seed;
+    let (escrow_pda, escrow_bump) = validate_escrow_accounts(
+        program_id,
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        mint_b.key,
     )?;
 
-    invoke_signed(
-        &instruction::close_account(
-            token_program.key,
-            escrow_vault.key,
+    if !escrow.is_expired(Clock::get()?.unix_timestamp) {
+        return Err(EscrowError::EscrowNotExpired.into());
+    }
+
+    if escrow.native_side == NativeSide::MintA {
+        if maker_token_a.key != maker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+        transfer_lamports(escrow_vault, maker, escrow.amount)?;
+        transfer_lamports(escrow_vault, rent_payer, escrow_vault.lamports())?;
+    } else {
+        if maker_token_a.owner != token_program.key {
+            return Err(EscrowError::InvalidTokenAccount.into());
+        }
+        let maker_token_a_account = unpack_token_account(maker_token_a)?;
+        if maker_token_a_account.owner != *maker.key {
+            return Err(EscrowError::InvalidUser.into());
+        }
+
+        invoke_signed(
+            &instruction::transfer_checked(
+                token_program.key,
+                escrow_vault.key,
+                mint_a.key,
+                maker_token_a.key,
+                &escrow_pda,
+                &[],
+                escrow.amount,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                escrow_vault.clone(),
+                mint_a.clone(),
+                maker_token_a.clone(),
+                escrow_state.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                mint_b.key.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ]],
+        )?;
+
+        close_token_vault(
+            token_program,
+            escrow_vault,
+            mint_a,
+            rent_payer,
+            escrow_state,
+            &[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                mint_b.key.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ],
+        )?;
+    }
+
+    let seed_bytes = seed.to_le_bytes();
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        maker.key.as_ref(),
+        mint_a.key.as_ref(),
+        mint_b.key.as_ref(),
+        &seed_bytes,
+        &[escrow_bump],
+    ];
+    for (leg, leg_accounts) in escrow.basket.iter().zip(&basket) {
+        release_basket_leg(
+            leg_accounts,
+            leg.amount,
             maker.key,
-            &escrow_pda,
-            &[],
-        )?,
-        &[
-            escrow_vault.clone(),
-            maker.clone(),
-            escrow_state.clone(),
-            token_program.clone(),
-        ],
-        &[&[
-            b"escrow",
-            maker.key.as_ref(),
-            mint_a.key.as_ref(),
-            mint_b.key.as_ref(),
-            &[escrow_bump],
-        ]],
-    )?;
+            escrow_state,
+            rent_payer,
+            escrow_seeds,
+        )?;
+    }
 
-    **maker.try_borrow_mut_lamports()? += escrow_state.lamports();
+    **rent_payer.try_borrow_mut_lamports()? += escrow_state.lamports();
     **escrow_state.try_borrow_mut_lamports()? = 0;
     escrow_state.data.borrow_mut().fill(0);
 
+    EscrowEvent::Refunded {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        mint_a: *mint_a.key,
+        mint_b: *mint_b.key,
+        amount: escrow.amount,
+    }
+    .emit();
+
     Ok(())
 }
+
+pub fn update(program_id: &Pubkey, accounts: &[AccountInfo], args: UpdateArgs) -> ProgramResult {
+    let UpdateArgs {
+        receive_amount,
+        deposit,
+        withdraw,
+        expires_at,
+    } = args;
+
+    if receive_amount == Some(0) || (deposit != 0 && withdraw != 0) {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    if let Some(Some(expires_at)) = expires_at {
+        if expires_at <= Clock::get()?.unix_timestamp {
+            return Err(EscrowError::InvalidExpiry.into());
+        }
+    }
+
+    let accs = &mut accounts.iter();
+
+    let maker = next_account_info(accs)?;
+    let mint_a = next_account_info(accs)?;
+    let mint_b = next_account_info(accs)?;
+    let maker_token_a = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+    let escrow_vault = next_account_info(accs)?;
+    let token_program = next_account_info(accs)?;
+    let system_program = next_account_info(accs)?;
+
+    if !maker.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    if system_program.key != &system_program::id() {
+        return Err(EscrowError::InvalidSystemProgram.into());
+    }
+    check_asset_program(token_program, mint_a)?;
+
+    let mut escrow = load_escrow(program_id, escrow_state)?;
+    check_swappable(&escrow)?;
+    // The auction sets mint B's price, so a fixed one would be ignored.
+    if receive_amount.is_some() && escrow.auction.is_some() {
+        return Err(EscrowError::AuctionActive.into());
+    }
+    let seed = escrow.seed;
+    let (escrow_pda, escrow_bump) = validate_escrow_accounts(
+        program_id,
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        mint_b.key,
+    )?;
+
+    // Withdrawing everything is a refund, which also closes the accounts.
+    if withdraw >= escrow.amount {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+
+    if escrow.native_side == NativeSide::MintA {
+        if maker_token_a.key != maker.key {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+        if deposit > 0 {
+            invoke(
+                &system_instruction::transfer(maker.key, escrow_vault.key, deposit),
+                &[maker.clone(), escrow_vault.clone(), system_program.clone()],
+            )?;
+        }
+        if withdraw > 0 {
+            transfer_lamports(escrow_vault, maker, withdraw)?;
+        }
+    } else if deposit > 0 {
+        invoke(
+            &instruction::transfer_checked(
+                token_program.key,
+                maker_token_a.key,
+                mint_a.key,
+                escrow_vault.key,
+                maker.key,
+                &[maker.key],
+                deposit,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                maker_token_a.clone(),
+                mint_a.clone(),
+                escrow_vault.clone(),
+                maker.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    } else if withdraw > 0 {
+        invoke_signed(
+            &instruction::transfer_checked(
+                token_program.key,
+                escrow_vault.key,
+                mint_a.key,
+                maker_token_a.key,
+                &escrow_pda,
+                &[],
+                withdraw,
+                mint_decimals(mint_a)?,
+            )?,
+            &[
+                escrow_vault.clone(),
+                mint_a.clone(),
+                maker_token_a.clone(),
+                escrow_state.clone(),
+                token_program.clone(),
+            ],
+            &[&[
+                b"escrow",
+                maker.key.as_ref(),
+                mint_a.key.as_ref(),
+                mint_b.key.as_ref(),
+                &seed.to_le_bytes(),
+                &[escrow_bump],
+            ]],
+        )?;
+    }
+
+    let amount = if escrow.native_side == NativeSide::MintA {
+        escrow
+            .amount
+            .checked_add(deposit)
+            .ok_or(EscrowError::Overflow)?
+            - withdraw
+    } else {
+        // Re-read the vault so a transfer fee on a top-up is reflected.
+        unpack_token_account(escrow_vault)?.amount
+    };
+    // The other prices are for the whole deposit and cannot be set here, so
+    // they keep their rate.
+    if let Some(auction) = &mut escrow.auction {
+        auction.start_price = scale_price(auction.start_price, amount, escrow.amount)?;
+        auction.end_price = scale_price(auction.end_price, amount, escrow.amount)?;
+    }
+    for option in &mut escrow.payment_options {
+        option.receive_amount = scale_price(option.receive_amount, amount, escrow.amount)?;
+    }
+    escrow.amount = amount;
+    if let Some(receive_amount) = receive_amount {
+        escrow.receive_amount = receive_amount;
+    }
+    if let Some(expires_at) = expires_at {
+        escrow.expires_at = expires_at;
+    }
+    escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
+
+    EscrowEvent::Updated {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        mint_a: *mint_a.key,
+        mint_b: *mint_b.key,
+        amount: escrow.amount,
+        receive_amount: escrow.receive_amount,
+        expires_at: escrow.expires_at,
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn set_payment_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    receive_amount: u64,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let maker = next_account_info(accs)?;
+    let mint = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+    let system_program = next_account_info(accs)?;
+
+    if !maker.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    if system_program.key != &system_program::id() {
+        return Err(EscrowError::InvalidSystemProgram.into());
+    }
+
+    let mut escrow = load_escrow(program_id, escrow_state)?;
+    check_swappable(&escrow)?;
+    if escrow.owner != *maker.key {
+        return Err(EscrowError::MakerMismatch.into());
+    }
+    if *mint.key == escrow.mint_a
+        || *mint.key == escrow.mint_b
+        || escrow.basket.iter().any(|leg| leg.mint == *mint.key)
+    {
+        return Err(EscrowError::InvalidPaymentMint.into());
+    }
+
+    if receive_amount == 0 {
+        escrow
+            .payment_options
+            .retain(|option| option.mint != *mint.key);
+    } else {
+        let mint_program = if is_native(mint) {
+            &system_program::ID
+        } else {
+            if mint.owner != &spl_token_interface::ID && mint.owner != &spl_token_2022_interface::ID
+            {
+                return Err(EscrowError::InvalidTokenProgram.into());
+            }
+            // Fails unless `mint` really is a mint.
+            mint_decimals(mint)?;
+            mint.owner
+        };
+        let config = load_config(program_id, config_account)?;
+        check_config_allows(config.as_ref(), &[mint_program])?;
+
+        let options = &mut escrow.payment_options;
+        if let Some(option) = options.iter_mut().find(|option| option.mint == *mint.key) {
+            option.receive_amount = receive_amount;
+        } else if options.len() >= Escrow::MAX_PAYMENT_OPTIONS {
+            return Err(EscrowError::TooManyPaymentMints.into());
+        } else {
+            options.push(PaymentOption {
+                mint: *mint.key,
+                receive_amount,
+            });
+        }
+    }
+    // A new option may not fit. Whoever paid the escrow's rent, and so gets
+    // it back at close, pays for the extra space too. Removing one leaves
+    // the space for the next.
+    let space = escrow.space();
+    if space > escrow_state.data_len() {
+        let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+        if !rent_payer.is_signer {
+            return Err(EscrowError::MissingSignature.into());
+        }
+        let top_up = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(escrow_state.lamports());
+        invoke(
+            &system_instruction::transfer(rent_payer.key, escrow_state.key, top_up),
+            &[
+                rent_payer.clone(),
+                escrow_state.clone(),
+                system_program.clone(),
+            ],
+        )?;
+        escrow_state.resize(space)?;
+    }
+    escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
+
+    EscrowEvent::PaymentMintSet {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        mint: *mint.key,
+        receive_amount,
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn set_auction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    auction: Option<Auction>,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let maker = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+
+    if !maker.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+
+    let mut escrow = load_escrow(program_id, escrow_state)?;
+    check_swappable(&escrow)?;
+    if escrow.owner != *maker.key {
+        return Err(EscrowError::MakerMismatch.into());
+    }
+    if let Some(auction) = auction {
+        if auction.end_price == 0
+            || auction.start_price <= auction.end_price
+            || auction.start_at >= auction.end_at
+            || auction.end_at <= Clock::get()?.unix_timestamp
+        {
+            return Err(EscrowError::InvalidAuction.into());
+        }
+    }
+
+    escrow.auction = auction;
+    escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
+
+    EscrowEvent::AuctionSet {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        auction,
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn settle(program_id: &Pubkey, accounts: &[AccountInfo], seller_amount: u64) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let signer = next_account_info(accs)?;
+    let maker = next_account_info(accs)?;
+    let mint_a = next_account_info(accs)?;
+    let seller_token_a = next_account_info(accs)?;
+    let maker_token_a = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+    let escrow_vault = next_account_info(accs)?;
+    let token_program = next_account_info(accs)?;
+
+    if !signer.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    check_asset_program(token_program, mint_a)?;
+
+    let escrow = load_escrow(program_id, escrow_state)?;
+    let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+    // Arbitrated payments have no mint B.
+    let mint_b = Pubkey::default();
+    let (_, escrow_bump) = validate_escrow_accounts(
+        program_id,
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        &mint_b,
+    )?;
+    let arbitration = escrow.arbitration.ok_or(EscrowError::InvalidRuling)?;
+    if seller_amount > escrow.amount {
+        return Err(EscrowError::AmountExceedsRemaining.into());
+    }
+    // Each party may only rule against themselves: the buyer by releasing
+    // everything, the seller by refunding everything. Only the arbiter can
+    // rule for one side or split the deposit.
+    let may_rule = if *signer.key == arbitration.arbiter {
+        true
+    } else if *signer.key == escrow.owner {
+        seller_amount == escrow.amount
+    } else if *signer.key == arbitration.seller {
+        seller_amount == 0
+    } else {
+        false
+    };
+    if !may_rule {
+        return Err(EscrowError::InvalidRuling.into());
+    }
+    let buyer_amount = escrow.amount - seller_amount;
+
+    let seed_bytes = escrow.seed.to_le_bytes();
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        maker.key.as_ref(),
+        mint_a.key.as_ref(),
+        mint_b.as_ref(),
+        &seed_bytes,
+        &[escrow_bump],
+    ];
+    let vault = VaultAccounts {
+        escrow_state,
+        vault: escrow_vault,
+        mint: mint_a,
+        token_program,
+        native: escrow.native_side == NativeSide::MintA,
+    };
+    if seller_amount > 0 {
+        pay_from_vault(
+            &vault,
+            seller_token_a,
+            &arbitration.seller,
+            seller_amount,
+            escrow_seeds,
+        )?;
+    }
+    if buyer_amount > 0 {
+        pay_from_vault(&vault, maker_token_a, maker.key, buyer_amount, escrow_seeds)?;
+    }
+    close_vault(&vault, rent_payer, escrow_seeds)?;
+
+    **rent_payer.try_borrow_mut_lamports()? += escrow_state.lamports();
+    **escrow_state.try_borrow_mut_lamports()? = 0;
+    escrow_state.data.borrow_mut().fill(0);
+
+    EscrowEvent::Settled {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        ruled_by: *signer.key,
+        seller: arbitration.seller,
+        seller_amount,
+        buyer_amount,
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn approve_milestone(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    index: u8,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let maker = next_account_info(accs)?;
+    let mint_a = next_account_info(accs)?;
+    let beneficiary_token_a = next_account_info(accs)?;
+    let escrow_state = next_account_info(accs)?;
+    let escrow_vault = next_account_info(accs)?;
+    let token_program = next_account_info(accs)?;
+
+    if !maker.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    check_asset_program(token_program, mint_a)?;
+
+    let mut escrow = load_escrow(program_id, escrow_state)?;
+    let rent_payer = next_rent_payer(accs, &escrow, maker)?;
+    // Milestone payments have no mint B.
+    let mint_b = Pubkey::default();
+    let (_, escrow_bump) = validate_escrow_accounts(
+        program_id,
+        &escrow,
+        escrow_state,
+        escrow_vault,
+        maker,
+        mint_a.key,
+        &mint_b,
+    )?;
+    let beneficiary = escrow
+        .beneficiary
+        .ok_or(EscrowError::MilestoneUnavailable)?;
+    let milestone = escrow
+        .milestones
+        .get_mut(index as usize)
+        .filter(|milestone| !milestone.released)
+        .ok_or(EscrowError::MilestoneUnavailable)?;
+    milestone.released = true;
+    let amount = milestone.amount;
+    escrow.amount -= amount;
+
+    let seed_bytes = escrow.seed.to_le_bytes();
+    let escrow_seeds: &[&[u8]] = &[
+        b"escrow",
+        maker.key.as_ref(),
+        mint_a.key.as_ref(),
+        mint_b.as_ref(),
+        &seed_bytes,
+        &[escrow_bump],
+    ];
+    let vault = VaultAccounts {
+        escrow_state,
+        vault: escrow_vault,
+        mint: mint_a,
+        token_program,
+        native: escrow.native_side == NativeSide::MintA,
+    };
+    pay_from_vault(
+        &vault,
+        beneficiary_token_a,
+        &beneficiary,
+        amount,
+        escrow_seeds,
+    )?;
+
+    // The last approval empties the vault, so the escrow closes with it.
+    if escrow.amount == 0 {
+        close_vault(&vault, rent_payer, escrow_seeds)?;
+        **rent_payer.try_borrow_mut_lamports()? += escrow_state.lamports();
+        **escrow_state.try_borrow_mut_lamports()? = 0;
+        escrow_state.data.borrow_mut().fill(0);
+    } else {
+        escrow.serialize(&mut &mut escrow_state.data.borrow_mut()[..])?;
+    }
+
+    EscrowEvent::MilestoneReleased {
+        escrow: *escrow_state.key,
+        maker: *maker.key,
+        beneficiary,
+        index,
+        amount,
+        remaining: escrow.amount,
+    }
+    .emit();
+
+    Ok(())
+}
+
+pub fn initialize_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_bps: u16,
+    treasury: Pubkey,
+    allowed_token_programs: Vec<Pubkey>,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let admin = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+    let system_program = next_account_info(accs)?;
+    let program_data = next_account_info(accs)?;
+
+    if !admin.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    if system_program.key != &system_program::id() {
+        return Err(EscrowError::InvalidSystemProgram.into());
+    }
+    check_upgrade_authority(program_id, program_data, admin.key)?;
+    if fee_bps > Config::MAX_FEE_BPS {
+        return Err(EscrowError::FeeTooHigh.into());
+    }
+    check_token_program_allowlist(&allowed_token_programs)?;
+
+    let (config_pda, config_bump) = Pubkey::find_program_address(&[b"config"], program_id);
+    if config_pda != *config_account.key {
+        return Err(EscrowError::InvalidConfig.into());
+    }
+    // The config can only be created once, by the upgrade authority, who
+    // becomes its admin.
+    if config_account.owner != &system_program::id() || !config_account.data_is_empty() {
+        return Err(EscrowError::ConfigAlreadyExists.into());
+    }
+
+    let config_seeds: &[&[u8]] = &[b"config", &[config_bump]];
+    let rent = Rent::get()?.minimum_balance(Config::LEN);
+    if config_account.lamports() == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                admin.key,
+                config_account.key,
+                rent,
+                Config::LEN as u64,
+                program_id,
+            ),
+            &[
+                admin.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+            &[config_seeds],
+        )?;
+    } else {
+        // Anyone can send lamports to the config address ahead of time, which
+        // makes `create_account` fail; top it up and take it over instead.
+        let top_up = rent.saturating_sub(config_account.lamports());
+        if top_up > 0 {
+            invoke(
+                &system_instruction::transfer(admin.key, config_account.key, top_up),
+                &[
+                    admin.clone(),
+                    config_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(config_account.key, Config::LEN as u64),
+            &[config_account.clone(), system_program.clone()],
+            &[config_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(config_account.key, program_id),
+            &[config_account.clone(), system_program.clone()],
+            &[config_seeds],
+        )?;
+    }
+
+    let config = Config {
+        account_type: AccountType::Config,
+        version: Config::VERSION,
+        admin: *admin.key,
+        treasury,
+        fee_bps,
+        paused: false,
+        bump: config_bump,
+        allowed_token_programs,
+    };
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+pub fn update_fee(program_id: &Pubkey, accounts: &[AccountInfo], fee_bps: u16) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let admin = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+
+    if fee_bps > Config::MAX_FEE_BPS {
+        return Err(EscrowError::FeeTooHigh.into());
+    }
+
+    let mut config = load_admin_config(program_id, admin, config_account)?;
+    config.fee_bps = fee_bps;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+pub fn set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let admin = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+
+    let mut config = load_admin_config(program_id, admin, config_account)?;
+    config.paused = paused;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+pub fn transfer_admin(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_admin: Pubkey,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let admin = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+
+    let mut config = load_admin_config(program_id, admin, config_account)?;
+    config.admin = new_admin;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+pub fn set_allowed_token_programs(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    allowed_token_programs: Vec<Pubkey>,
+) -> ProgramResult {
+    let accs = &mut accounts.iter();
+
+    let admin = next_account_info(accs)?;
+    let config_account = next_account_info(accs)?;
+
+    check_token_program_allowlist(&allowed_token_programs)?;
+
+    let mut config = load_admin_config(program_id, admin, config_account)?;
+    config.allowed_token_programs = allowed_token_programs;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+/// An empty allowlist would block every SPL escrow, so at least one token
+/// program must be listed.
+fn check_token_program_allowlist(allowed_token_programs: &[Pubkey]) -> ProgramResult {
+    if allowed_token_programs.is_empty() {
+        return Err(EscrowError::NoTokenPrograms.into());
+    }
+    if allowed_token_programs.len() > Config::MAX_TOKEN_PROGRAMS {
+        return Err(EscrowError::TooManyTokenPrograms.into());
+    }
+    Ok(())
+}
+
+/// Reads an escrow account, refusing data that this program does not own.
+fn load_escrow(program_id: &Pubkey, escrow_state: &AccountInfo) -> Result<Escrow, ProgramError> {
+    if escrow_state.owner != program_id {
+        return Err(EscrowError::InvalidEscrowOwner.into());
+    }
+    Escrow::unpack(&escrow_state.data.borrow())
+}
+
+/// Returns the account that gets the escrow's rent back when it closes: an
+/// optional trailing account, or the maker when none is passed.
+fn next_rent_payer<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    accs: &mut I,
+    escrow: &Escrow,
+    maker: &'a AccountInfo<'b>,
+) -> Result<&'a AccountInfo<'b>, ProgramError> {
+    let rent_payer = accs.next().unwrap_or(maker);
+    if *rent_payer.key != escrow.rent_payer {
+        return Err(EscrowError::InvalidRentPayer.into());
+    }
+    Ok(rent_payer)
+}
+
+/// Checks that the maker, mints, escrow and vault passed in are the ones the
+/// escrow was created with, returning the escrow address and bump.
+///
+/// Native SOL leaves the vault without a PDA signature, so these checks are
+/// what stops funds moving out of an account that merely looks like an escrow.
+fn validate_escrow_accounts(
+    program_id: &Pubkey,
+    escrow: &Escrow,
+    escrow_state: &AccountInfo,
+    escrow_vault: &AccountInfo,
+    maker: &AccountInfo,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+) -> Result<(Pubkey, u8), ProgramError> {
+    if escrow.owner != *maker.key {
+        return Err(EscrowError::MakerMismatch.into());
+    }
+    if escrow.mint_a != *mint_a || escrow.mint_b != *mint_b {
+        return Err(EscrowError::MintMismatch.into());
+    }
+
+    // The bumps were found once in `make`; re-deriving with them is much
+    // cheaper than searching again, and a tampered bump simply yields a
+    // different address.
+    let escrow_pda = Pubkey::create_program_address(
+        &[
+            b"escrow",
+            maker.key.as_ref(),
+            mint_a.as_ref(),
+            mint_b.as_ref(),
+            &escrow.seed.to_le_bytes(),
+            &[escrow.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| EscrowError::InvalidEscrowAddress)?;
+    if escrow_pda != *escrow_state.key {
+        return Err(EscrowError::InvalidEscrowAddress.into());
+    }
+
+    let vault_pda = Pubkey::create_program_address(
+        &[b"vault", escrow_state.key.as_ref(), &[escrow.vault_bump]],
+        program_id,
+    )
+    .map_err(|_| EscrowError::InvalidVault)?;
+    if vault_pda != *escrow_vault.key {
+        return Err(EscrowError::InvalidVault.into());
+    }
+
+    Ok((escrow_pda, escrow.bump))
+}
+
+/// The accounts passed for one basket leg.
+struct BasketAccounts<'a, 'b> {
+    mint: &'a AccountInfo<'b>,
+    /// Token account of whoever funds or receives the leg: the maker in make
+    /// and on a refund, or the taker.
+    token_account: &'a AccountInfo<'b>,
+    vault: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+}
+
+/// Reads the mint, token account, vault and token program of every basket leg,
+/// in the order the escrow lists them, checking each against the escrow.
+fn next_basket_accounts<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    accs: &mut I,
+    program_id: &Pubkey,
+    escrow: &Escrow,
+    escrow_state: &AccountInfo,
+) -> Result<Vec<BasketAccounts<'a, 'b>>, ProgramError> {
+    escrow
+        .basket
+        .iter()
+        .map(|leg| {
+            let accounts = BasketAccounts {
+                mint: next_account_info(accs)?,
+                token_account: next_account_info(accs)?,
+                vault: next_account_info(accs)?,
+                token_program: next_account_info(accs)?,
+            };
+            if *accounts.mint.key != leg.mint {
+                return Err(EscrowError::MintMismatch.into());
+            }
+            check_asset_program(accounts.token_program, accounts.mint)?;
+
+            let vault_pda = Pubkey::create_program_address(
+                &[
+                    b"vault",
+                    escrow_state.key.as_ref(),
+                    leg.mint.as_ref(),
+                    &[leg.vault_bump],
+                ],
+                program_id,
+            )
+            .map_err(|_| EscrowError::InvalidVault)?;
+            if vault_pda != *accounts.vault.key {
+                return Err(EscrowError::InvalidVault.into());
+            }
+            Ok(accounts)
+        })
+        .collect()
+}
+
+/// Opens the vault of a basket leg, funded by `rent_payer`, and deposits
+/// `amount` of its mint from the maker.
+fn deposit_basket_leg<'a>(
+    program_id: &Pubkey,
+    leg: &BasketAccounts<'_, 'a>,
+    amount: u64,
+    maker: &AccountInfo<'a>,
+    rent_payer: &AccountInfo<'a>,
+    escrow_state: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> Result<BasketLeg, ProgramError> {
+    check_asset_program(leg.token_program, leg.mint)?;
+    if leg.token_account.owner != leg.token_program.key {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    let maker_token_account = unpack_token_account(leg.token_account)?;
+    if maker_token_account.owner != *maker.key {
+        return Err(EscrowError::InvalidUser.into());
+    }
+    if maker_token_account.mint != *leg.mint.key {
+        return Err(EscrowError::InvalidMint.into());
+    }
+    if maker_token_account.amount < amount {
+        return Err(EscrowError::InsufficientFunds.into());
+    }
+
+    let (vault_pda, vault_bump) = Pubkey::find_program_address(
+        &[b"vault", escrow_state.key.as_ref(), leg.mint.key.as_ref()],
+        program_id,
+    );
+    if vault_pda != *leg.vault.key {
+        return Err(EscrowError::InvalidVault.into());
+    }
+    if leg.vault.lamports() != 0 {
+        return Err(EscrowError::EscrowAlreadyExists.into());
+    }
+
+    create_token_vault(
+        rent_payer,
+        leg.vault,
+        leg.mint,
+        escrow_state,
+        leg.token_program,
+        system_program,
+        &[
+            b"vault",
+            escrow_state.key.as_ref(),
+            leg.mint.key.as_ref(),
+            &[vault_bump],
+        ],
+    )?;
+
+    invoke(
+        &instruction::transfer_checked(
+            leg.token_program.key,
+            leg.token_account.key,
+            leg.mint.key,
+            leg.vault.key,
+            maker.key,
+            &[maker.key],
+            amount,
+            mint_decimals(leg.mint)?,
+        )?,
+        &[
+            leg.token_account.clone(),
+            leg.mint.clone(),
+            leg.vault.clone(),
+            maker.clone(),
+            leg.token_program.clone(),
+        ],
+    )?;
+
+    let deposited = unpack_token_account(leg.vault)?.amount;
+    if deposited == 0 {
+        return Err(EscrowError::InvalidAmount.into());
+    }
+    Ok(BasketLeg {
+        mint: *leg.mint.key,
+        amount: deposited,
+        vault_bump,
+    })
+}
+
+/// Sends `amount` from a basket leg's vault to `recipient`'s token account and
+/// closes the vault, returning its rent to `rent_payer`.
+fn release_basket_leg<'a>(
+    leg: &BasketAccounts<'_, 'a>,
+    amount: u64,
+    recipient: &Pubkey,
+    escrow_state: &AccountInfo<'a>,
+    rent_payer: &AccountInfo<'a>,
+    escrow_seeds: &[&[u8]],
+) -> ProgramResult {
+    if leg.token_account.owner != leg.token_program.key {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    if unpack_token_account(leg.token_account)?.owner != *recipient {
+        return Err(EscrowError::InvalidUser.into());
+    }
+
+    invoke_signed(
+        &instruction::transfer_checked(
+            leg.token_program.key,
+            leg.vault.key,
+            leg.mint.key,
+            leg.token_account.key,
+            escrow_state.key,
+            &[],
+            amount,
+            mint_decimals(leg.mint)?,
+        )?,
+        &[
+            leg.vault.clone(),
+            leg.mint.clone(),
+            leg.token_account.clone(),
+            escrow_state.clone(),
+            leg.token_program.clone(),
+        ],
+        &[escrow_seeds],
+    )?;
+
+    close_token_vault(
+        leg.token_program,
+        leg.vault,
+        leg.mint,
+        rent_payer,
+        escrow_state,
+        escrow_seeds,
+    )
+}
+
+/// The escrow's main vault, which holds mint A.
+struct VaultAccounts<'a, 'b> {
+    escrow_state: &'a AccountInfo<'b>,
+    vault: &'a AccountInfo<'b>,
+    mint: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+    /// Mint A is native SOL, held as the vault's own lamports.
+    native: bool,
+}
+
+/// Sends `amount` from the vault to `recipient`, through their token account
+/// or, for native SOL, straight to their wallet.
+fn pay_from_vault<'a>(
+    vault: &VaultAccounts<'_, 'a>,
+    recipient_account: &AccountInfo<'a>,
+    recipient: &Pubkey,
+    amount: u64,
+    escrow_seeds: &[&[u8]],
+) -> ProgramResult {
+    if vault.native {
+        if recipient_account.key != recipient {
+            return Err(EscrowError::InvalidNativeAccount.into());
+        }
+        return transfer_lamports(vault.vault, recipient_account, amount);
+    }
+
+    if recipient_account.owner != vault.token_program.key {
+        return Err(EscrowError::InvalidTokenAccount.into());
+    }
+    if unpack_token_account(recipient_account)?.owner != *recipient {
+        return Err(EscrowError::InvalidUser.into());
+    }
+    invoke_signed(
+        &instruction::transfer_checked(
+            vault.token_program.key,
+            vault.vault.key,
+            vault.mint.key,
+            recipient_account.key,
+            vault.escrow_state.key,
+            &[],
+            amount,
+            mint_decimals(vault.mint)?,
+        )?,
+        &[
+            vault.vault.clone(),
+            vault.mint.clone(),
+            recipient_account.clone(),
+            vault.escrow_state.clone(),
+            vault.token_program.clone(),
+        ],
+        &[escrow_seeds],
+    )
+}
+
+/// Closes an emptied vault, returning its rent to `rent_payer`.
+fn close_vault<'a>(
+    vault: &VaultAccounts<'_, 'a>,
+    rent_payer: &AccountInfo<'a>,
+    escrow_seeds: &[&[u8]],
+) -> ProgramResult {
+    if vault.native {
+        return transfer_lamports(vault.vault, rent_payer, vault.vault.lamports());
+    }
+    close_token_vault(
+        vault.token_program,
+        vault.vault,
+        vault.mint,
+        rent_payer,
+        vault.escrow_state,
+        escrow_seeds,
+    )
+}
+
+/// Closes an emptied token vault owned by the escrow, returning its rent to
+/// `rent_payer`. Token-2022 withholds transfer fees in the receiving account
+/// and refuses to close it until they are harvested to the mint, which anyone
+/// may do.
+fn close_token_vault<'a>(
+    token_program: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    rent_payer: &AccountInfo<'a>,
+    escrow_state: &AccountInfo<'a>,
+    escrow_seeds: &[&[u8]],
+) -> ProgramResult {
+    let withheld = StateWithExtensions::<TokenAccount>::unpack(&vault.data.borrow())?
+        .get_extension::<TransferFeeAmount>()
+        .map_or(0, |fee| u64::from(fee.withheld_amount));
+    if withheld > 0 {
+        invoke(
+            &transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                token_program.key,
+                mint.key,
+                &[vault.key],
+            )?,
+            &[mint.clone(), vault.clone(), token_program.clone()],
+        )?;
+    }
+
+    invoke_signed(
+        &instruction::close_account(
+            token_program.key,
+            vault.key,
+            rent_payer.key,
+            escrow_state.key,
+            &[],
+        )?,
+        &[
+            vault.clone(),
+            rent_payer.clone(),
+            escrow_state.clone(),
+            token_program.clone(),
+        ],
+        &[escrow_seeds],
+    )
+}
+
+/// Arbitrated escrows are settled only by [`settle`], so every other
+/// instruction on an escrow rejects them.
+fn check_not_arbitrated(escrow: &Escrow) -> ProgramResult {
+    if escrow.arbitration.is_some() {
+        return Err(EscrowError::Arbitrated.into());
+    }
+    Ok(())
+}
+
+/// Only plain swaps can be taken or have their terms changed; arbitrated and
+/// milestone escrows pay out through their own instructions.
+fn check_swappable(escrow: &Escrow) -> ProgramResult {
+    check_not_arbitrated(escrow)?;
+    if !escrow.milestones.is_empty() {
+        return Err(EscrowError::HasMilestones.into());
+    }
+    Ok(())
+}
+
+/// Checks each milestone has a short name and a non-zero amount, and that
+/// their total fits in a `u64`; `make` compares it with the deposit.
+fn check_milestones(milestones: Vec<MilestoneArgs>) -> Result<Vec<Milestone>, ProgramError> {
+    if milestones.is_empty() {
+        return Err(EscrowError::InvalidMilestones.into());
+    }
+    if milestones.len() > Escrow::MAX_MILESTONES {
+        return Err(EscrowError::TooManyMilestones.into());
+    }
+    let mut total: u64 = 0;
+    for milestone in &milestones {
+        if milestone.name.is_empty()
+            || milestone.name.len() > Milestone::MAX_NAME_LEN
+            || milestone.amount == 0
+        {
+            return Err(EscrowError::InvalidMilestones.into());
+        }
+        total = total
+            .checked_add(milestone.amount)
+            .ok_or(EscrowError::Overflow)?;
+    }
+    Ok(milestones
+        .into_iter()
+        .map(|milestone| Milestone {
+            name: milestone.name,
+            amount: milestone.amount,
+            released: false,
+        })
+        .collect())
+}
+
+/// Reads the protocol config, or `None` while it has not been initialized.
+/// Either way the account must sit at the config PDA, so a taker cannot dodge
+/// the fee by passing some other empty account.
+fn load_config(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<Option<Config>, ProgramError> {
+    if config_account.owner != program_id {
+        let (config_pda, _) = Pubkey::find_program_address(&[b"config"], program_id);
+        if config_pda != *config_account.key {
+            return Err(EscrowError::InvalidConfig.into());
+        }
+        return Ok(None);
+    }
+
+    let config = Config::unpack(&config_account.data.borrow())?;
+    let config_pda = Pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+        .map_err(|_| EscrowError::InvalidConfig)?;
+    if config_pda != *config_account.key {
+        return Err(EscrowError::InvalidConfig.into());
+    }
+    Ok(Some(config))
+}
+
+/// Checks that `authority` is the upgrade authority recorded in the
+/// program's ProgramData account, so only whoever deployed the program can
+/// claim its config.
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    program_data: &AccountInfo,
+    authority: &Pubkey,
+) -> ProgramResult {
+    if program_data.owner != &solana_sdk_ids::bpf_loader_upgradeable::ID
+        || *program_data.key != get_program_data_address(program_id)
+    {
+        return Err(EscrowError::InvalidProgramData.into());
+    }
+    let data = program_data.data.borrow();
+    let metadata = data
+        .get(..UpgradeableLoaderState::size_of_programdata_metadata())
+        .ok_or(EscrowError::InvalidProgramData)?;
+    match bincode::deserialize(metadata) {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address: Some(upgrade_authority),
+            ..
+        }) if upgrade_authority == *authority => Ok(()),
+        Ok(UpgradeableLoaderState::ProgramData { .. }) => {
+            Err(EscrowError::NotUpgradeAuthority.into())
+        }
+        _ => Err(EscrowError::InvalidProgramData.into()),
+    }
+}
+
+/// Reads the config for an admin-only instruction, checking that `admin`
+/// signed and is the current admin.
+fn load_admin_config(
+    program_id: &Pubkey,
+    admin: &AccountInfo,
+    config_account: &AccountInfo,
+) -> Result<Config, ProgramError> {
+    if !admin.is_signer {
+        return Err(EscrowError::MissingSignature.into());
+    }
+    let config = load_config(program_id, config_account)?.ok_or(EscrowError::InvalidConfig)?;
+    if config.admin != *admin.key {
+        return Err(EscrowError::Unauthorized.into());
+    }
+    Ok(config)
+}
+
+/// Applies the config's pause switch and token program allowlist to a new or
+/// filled escrow. Native SOL moves through the system program and is always
+/// allowed.
+fn check_config_allows(config: Option<&Config>, token_programs: &[&Pubkey]) -> ProgramResult {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    if config.paused {
+        return Err(EscrowError::ProgramPaused.into());
+    }
+    for token_program in token_programs {
+        if **token_program != system_program::id() && !config.allows_token_program(token_program) {
+            return Err(EscrowError::TokenProgramNotAllowed.into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks the program moving one side of the escrow: the system program for
+/// native SOL, otherwise the legacy token program or Token-2022, as long as it
+/// is the program that owns `mint`.
+fn check_asset_program(token_program: &AccountInfo, mint: &AccountInfo) -> ProgramResult {
+    if is_native(mint) {
+        if token_program.key != &system_program::id() {
+            return Err(EscrowError::InvalidTokenProgram.into());
+        }
+        return Ok(());
+    }
+    if token_program.key != &spl_token_interface::ID
+        && token_program.key != &spl_token_2022_interface::ID
+    {
+        return Err(EscrowError::InvalidTokenProgram.into());
+    }
+    if mint.owner != token_program.key {
+        return Err(EscrowError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+/// Creates the token account at the vault PDA signed for by `vault_seeds`,
+/// owned by the escrow and funded by `payer`.
+fn create_token_vault<'a>(
+    payer: &AccountInfo<'a>,
+    vault: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    escrow_state: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    vault_seeds: &[&[u8]],
+) -> ProgramResult {
+    // Token-2022 mints may require extensions on every account that holds
+    // them, so the vault is sized from the mint rather than a fixed length.
+    let vault_len = {
+        let mint_data = mint.data.borrow();
+        let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+        let required_extensions =
+            ExtensionType::get_required_init_account_extensions(&mint_state.get_extension_types()?);
+        ExtensionType::try_calculate_account_len::<TokenAccount>(&required_extensions)?
+    };
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            vault.key,
+            Rent::get()?.minimum_balance(vault_len),
+            vault_len as u64,
+            token_program.key,
+        ),
+        &[payer.clone(), vault.clone(), system_program.clone()],
+        &[vault_seeds],
+    )?;
+
+    invoke(
+        &instruction::initialize_account3(
+            token_program.key,
+            vault.key,
+            mint.key,
+            escrow_state.key,
+        )?,
+        &[
+            vault.clone(),
+            mint.clone(),
+            escrow_state.clone(),
+            token_program.clone(),
+        ],
+    )
+}
+
+/// Creates `owner`'s associated token account for `mint`, funded by `payer`,
+/// unless `account` already exists. The associated token program rejects an
+/// `account` that is not that address.
+fn create_token_account_if_missing<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    owner: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    associated_token_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    if !account.data_is_empty() {
+        return Ok(());
+    }
+    invoke(
+        &create_associated_token_account_idempotent(
+            payer.key,
+            owner.key,
+            mint.key,
+            token_program.key,
+        ),
+        &[
+            payer.clone(),
+            account.clone(),
+            owner.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+    )
+}
+
+fn is_native(mint: &AccountInfo) -> bool {
+    mint.key == &native_mint::ID
+}
+
+/// Moves lamports out of an account owned by this program.
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(EscrowError::InsufficientFunds)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(EscrowError::Overflow)?;
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
+
+fn unpack_token_account(account: &AccountInfo) -> Result<TokenAccount, ProgramError> {
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&account.data.borrow())?.base)
+}
+
+fn mint_decimals(mint: &AccountInfo) -> Result<u8, ProgramError> {
+    Ok(StateWithExtensions::<Mint>::unpack(&mint.data.borrow())?
+        .base
+        .decimals)
+}
+
+/// Scales `price` to a deposit that changed from `deposit` to `remaining`,
+/// rounding up so the rate never moves against the maker.
+fn scale_price(price: u64, remaining: u64, deposit: u64) -> Result<u64, ProgramError> {
+    u64::try_from((price as u128 * remaining as u128).div_ceil(deposit as u128))
+        .map_err(|_| EscrowError::Overflow.into())
+}
+
+/// Returns the amount that has to be sent so that `net_amount` arrives after
+/// any Token-2022 transfer fee configured on `mint`.
+fn pre_fee_amount(mint: &AccountInfo, net_amount: u64) -> Result<u64, ProgramError> {
+    let mint_data = mint.data.borrow();
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+    let Ok(fee_config) = mint_state.get_extension::<TransferFeeConfig>() else {
+        return Ok(net_amount);
+    };
+    let fee = fee_config
+        .calculate_inverse_epoch_fee(Clock::get()?.epoch, net_amount)
+        .ok_or(EscrowError::Overflow)?;
+    net_amount
+        .checked_add(fee)
+        .ok_or(EscrowError::Overflow.into())
+}
@@ -1,17 +1,288 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_borsh::v1::try_from_slice_unchecked;
+use solana_program_error::ProgramError;
 use solana_pubkey::Pubkey;
 
+use crate::error::EscrowError;
+
+/// Tags every account owned by the program. Stored as the first byte of the
+/// account data so account kinds can never be confused for one another.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountType {
+    Uninitialized,
+    Escrow,
+    Config,
+}
+
+/// Whether `Escrow::receive_amount` is measured before or after any Token-2022
+/// transfer fee on mint B.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeMode {
+    /// The taker sends `receive_amount`; the maker receives it less fees.
+    Gross,
+    /// The maker receives `receive_amount`; the taker pays the fees on top.
+    Net,
+}
+
+/// Which side of the escrow, if any, is native SOL rather than an SPL token.
+///
+/// A native side uses the native mint address in place of the mint, and its
+/// lamports are held directly by the program-owned vault.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeSide {
+    None,
+    MintA,
+    MintB,
+}
+
+/// A mint offered alongside mint A, held in its own vault at
+/// `["vault", escrow, mint]` and released in full with the rest of the escrow.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BasketLeg {
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub vault_bump: u8,
+}
+
+impl BasketLeg {
+    pub const LEN: usize = 32 + 8 + 1; // mint + amount + vault_bump
+}
+
+/// Another mint the escrow can be paid in, and how much of it buys the whole
+/// remaining deposit.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PaymentOption {
+    pub mint: Pubkey,
+    pub receive_amount: u64,
+}
+
+impl PaymentOption {
+    pub const LEN: usize = 32 + 8; // mint + receive_amount
+}
+
+/// A descending price for the remaining deposit in mint B: `start_price`
+/// until `start_at`, falling linearly to `end_price` at `end_at` and staying
+/// there.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Auction {
+    pub start_price: u64,
+    pub end_price: u64,
+    pub start_at: i64,
+    pub end_at: i64,
+}
+
+impl Auction {
+    pub const LEN: usize = 8 + 8 + 8 + 8; // start_price + end_price + start_at + end_at
+
+    /// The price at unix time `now`, rounded up. The arithmetic is widened so
+    /// it cannot overflow for any timestamps or prices.
+    pub fn price_at(&self, now: i64) -> u64 {
+        if now <= self.start_at || self.end_at <= self.start_at {
+            return self.start_price;
+        }
+        if now >= self.end_at {
+            return self.end_price;
+        }
+        let elapsed = (now as i128 - self.start_at as i128) as u128;
+        let duration = (self.end_at as i128 - self.start_at as i128) as u128;
+        let drop = self.start_price.saturating_sub(self.end_price) as u128;
+        // `elapsed < duration`, so the discount is below `drop` and fits.
+        self.start_price - (drop * elapsed / duration) as u64
+    }
+}
+
+/// Turns an escrow into a payment held for `seller`: the maker is the buyer,
+/// and the deposit leaves the vault only by a ruling. The buyer may release it
+/// to the seller and the seller may refund it, while the arbiter may also
+/// split it between them.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arbitration {
+    pub arbiter: Pubkey,
+    pub seller: Pubkey,
+}
+
+impl Arbitration {
+    pub const LEN: usize = 32 + 32; // arbiter + seller
+}
+
+/// A named share of the deposit, paid to the escrow's beneficiary once the
+/// maker approves it.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Milestone {
+    pub name: String,
+    pub amount: u64,
+    pub released: bool,
+}
+
+impl Milestone {
+    pub const MAX_NAME_LEN: usize = 32;
+
+    pub const LEN: usize = (4 + Self::MAX_NAME_LEN) + 8 + 1; // name + amount + released
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub struct Escrow {
+    pub account_type: AccountType,
+    pub version: u8,
     pub owner: Pubkey,
+    /// Funded the escrow and vault rent in `make` and gets it back when they
+    /// close. Usually the maker, unless a relayer sponsored the rent.
+    pub rent_payer: Pubkey,
+    pub seed: u64,
     pub mint_a: Pubkey,
     pub mint_b: Pubkey,
     pub amount: u64,
     pub receive_amount: u64,
+    pub expires_at: Option<i64>,
+    pub allowed_taker: Option<Pubkey>,
+    pub receive_fee_mode: FeeMode,
+    pub native_side: NativeSide,
     pub bump: u8,
     pub vault_bump: u8,
+    /// Further mints bundled with mint A. An escrow with a basket can only be
+    /// taken whole, so the bundle is never split between takers.
+    pub basket: Vec<BasketLeg>,
+    /// Mints accepted instead of mint B. Like `receive_amount`, each price is
+    /// for everything still in the vault.
+    pub payment_options: Vec<PaymentOption>,
+    /// When set, mint B is sold by Dutch auction and `receive_amount` is
+    /// ignored. Payment options keep their fixed prices.
+    pub auction: Option<Auction>,
+    /// When set, the escrow cannot be taken, changed or refunded; see
+    /// [`Arbitration`].
+    pub arbitration: Option<Arbitration>,
+    /// Receives each milestone as it is approved.
+    pub beneficiary: Option<Pubkey>,
+    /// When not empty, the deposit is split into these milestones and can
+    /// only be approved out to the beneficiary or refunded; `amount` is what
+    /// is still unreleased.
+    pub milestones: Vec<Milestone>,
 }
 
 impl Escrow {
-    pub const LEN: usize = 32 + 32 + 32 + 8 + 8 + 1 + 1; // owner + mint_a + mint_b + amount + receive_amount + bump + vault_bump
+    pub const VERSION: u8 = 7;
+
+    pub const MAX_BASKET_LEGS: usize = 4;
+
+    pub const MAX_PAYMENT_OPTIONS: usize = 4;
+
+    pub const MAX_MILESTONES: usize = 8;
+
+    /// Everything but the basket legs, payment options, arbitration,
+    /// beneficiary and milestones, which only take space when used. The
+    /// expiry and auction can be set after `make`, so room is always kept for
+    /// them; the allowed taker, fixed at `make`, is counted at full size too.
+    const BASE_LEN: usize = 1
+        + 1
+        + 32
+        + 32
+        + 8
+        + 32
+        + 32
+        + 8
+        + 8
+        + (1 + 8)
+        + (1 + 32)
+        + 1
+        + 1
+        + 1
+        + 1
+        + 4
+        + 4
+        + (1 + Auction::LEN)
+        + 1
+        + 1
+        + 4; // account_type + version + owner + rent_payer + seed + mint_a + mint_b + amount + receive_amount + expires_at + allowed_taker + receive_fee_mode + native_side + bump + vault_bump + basket length + payment_options length + auction + arbitration tag + beneficiary tag + milestones length
+
+    /// The account size this escrow needs. `make` allocates just that, and
+    /// `set_payment_mint` grows the account as payment options are added.
+    pub fn space(&self) -> usize {
+        Self::BASE_LEN
+            + BasketLeg::LEN * self.basket.len()
+            + PaymentOption::LEN * self.payment_options.len()
+            + self.arbitration.map_or(0, |_| Arbitration::LEN)
+            + self.beneficiary.map_or(0, |_| 32)
+            + Milestone::LEN * self.milestones.len()
+    }
+
+    /// Deserializes an escrow account, rejecting data written for another
+    /// account type or an unknown layout version.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        match data.first() {
+            Some(account_type) if *account_type == AccountType::Escrow as u8 => {}
+            _ => return Err(EscrowError::InvalidAccountType.into()),
+        }
+        if data.get(1) != Some(&Self::VERSION) {
+            return Err(EscrowError::UnsupportedVersion.into());
+        }
+        Ok(try_from_slice_unchecked(data)?)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// What the remaining deposit costs in `mint`, or `None` if the escrow
+    /// cannot be paid in it.
+    pub fn receive_amount_in(&self, mint: &Pubkey) -> Option<u64> {
+        if *mint == self.mint_b {
+            return Some(self.receive_amount);
+        }
+        self.payment_options
+            .iter()
+            .find(|option| option.mint == *mint)
+            .map(|option| option.receive_amount)
+    }
+}
+
+/// Program-wide settings, stored at the PDA `["config"]`. Until it is
+/// initialized, fills pay no protocol fee and any token program is accepted.
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct Config {
+    pub account_type: AccountType,
+    pub version: u8,
+    pub admin: Pubkey,
+    /// Wallet that owns the token accounts protocol fees are paid into.
+    pub treasury: Pubkey,
+    /// Share of every mint B payment diverted to the treasury.
+    pub fee_bps: u16,
+    /// Emergency stop: while set, no escrow can be made or taken, but makers
+    /// can still refund.
+    pub paused: bool,
+    pub bump: u8,
+    /// Token programs escrowed mints may belong to. Native SOL is always
+    /// allowed.
+    pub allowed_token_programs: Vec<Pubkey>,
+}
+
+impl Config {
+    pub const VERSION: u8 = 1;
+
+    /// Upper bound on `fee_bps`, so the admin can never take more than 10%.
+    pub const MAX_FEE_BPS: u16 = 1_000;
+
+    pub const MAX_TOKEN_PROGRAMS: usize = 4;
+
+    pub const LEN: usize = 1 + 1 + 32 + 32 + 2 + 1 + 1 + (4 + 32 * Self::MAX_TOKEN_PROGRAMS); // account_type + version + admin + treasury + fee_bps + paused + bump + allowed_token_programs
+
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        match data.first() {
+            Some(account_type) if *account_type == AccountType::Config as u8 => {}
+            _ => return Err(EscrowError::InvalidAccountType.into()),
+        }
+        if data.get(1) != Some(&Self::VERSION) {
+            return Err(EscrowError::UnsupportedVersion.into());
+        }
+        Ok(try_from_slice_unchecked(data)?)
+    }
+
+    /// Protocol fee owed on a payment of `amount`, rounded up so that any
+    /// non-zero fee rate charges something.
+    pub fn fee_for(&self, amount: u64) -> u64 {
+        (amount as u128 * self.fee_bps as u128).div_ceil(10_000) as u64
+    }
+
+    pub fn allows_token_program(&self, program: &Pubkey) -> bool {
+        self.allowed_token_programs.contains(program)
+    }
 }
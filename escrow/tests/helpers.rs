@@ -3,23 +3,91 @@ use litesvm_token::{CreateAssociatedTokenAccount, CreateMint, MintTo};
 use solana_keypair::Keypair;
 use solana_pubkey::Pubkey;
 use solana_signer::Signer;
+use solana_system_interface::instruction::create_account;
+use solana_transaction::Transaction;
+use spl_token_2022_interface::{
+    extension::{
+        transfer_fee::instruction::initialize_transfer_fee_config, ExtensionType,
+        StateWithExtensions,
+    },
+    instruction::initialize_mint2,
+    state::{Account as TokenAccount, Mint},
+};
 
-pub fn create_mint(svm: &mut LiteSVM, decimals: u8, authority: &Keypair) -> Pubkey {
+pub fn create_mint(
+    svm: &mut LiteSVM,
+    decimals: u8,
+    authority: &Keypair,
+    token_program: &Pubkey,
+) -> Pubkey {
     CreateMint::new(svm, authority)
         .decimals(decimals)
         .authority(&authority.pubkey())
+        .token_program_id(token_program)
         .send()
         .unwrap()
 }
 
+pub fn create_mint_with_transfer_fee(
+    svm: &mut LiteSVM,
+    decimals: u8,
+    authority: &Keypair,
+    transfer_fee_basis_points: u16,
+    maximum_fee: u64,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let mint_len =
+        ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+            .unwrap();
+
+    let instructions = [
+        create_account(
+            &authority.pubkey(),
+            &mint.pubkey(),
+            svm.minimum_balance_for_rent_exemption(mint_len),
+            mint_len as u64,
+            &spl_token_2022_interface::ID,
+        ),
+        initialize_transfer_fee_config(
+            &spl_token_2022_interface::ID,
+            &mint.pubkey(),
+            Some(&authority.pubkey()),
+            Some(&authority.pubkey()),
+            transfer_fee_basis_points,
+            maximum_fee,
+        )
+        .unwrap(),
+        initialize_mint2(
+            &spl_token_2022_interface::ID,
+            &mint.pubkey(),
+            &authority.pubkey(),
+            None,
+            decimals,
+        )
+        .unwrap(),
+    ];
+
+    let tx = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&authority.pubkey()),
+        &[authority, &mint],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).unwrap();
+
+    mint.pubkey()
+}
+
 pub fn create_token_account(
     svm: &mut LiteSVM,
     payer: &Keypair,
     owner: &Pubkey,
     mint: &Pubkey,
+    token_program: &Pubkey,
 ) -> Pubkey {
     CreateAssociatedTokenAccount::new(svm, payer, mint)
         .owner(owner)
+        .token_program_id(token_program)
         .send()
         .unwrap()
 }
@@ -31,21 +99,39 @@ pub fn mint_tokens(
     authority: &Keypair,
     destination: &Pubkey,
     amount: u64,
+    token_program: &Pubkey,
 ) {
     MintTo::new(svm, payer, mint, destination, amount)
         .owner(authority)
+        .token_program_id(token_program)
         .send()
         .unwrap();
 }
 
+/// Reads the balance of a legacy or Token-2022 token account.
+pub fn token_balance(svm: &LiteSVM, token_account: &Pubkey) -> u64 {
+    let account = svm.get_account(token_account).unwrap();
+    StateWithExtensions::<TokenAccount>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount
+}
+
 pub fn derive_escrow_pda(
     program_id: &Pubkey,
     maker: &Pubkey,
     mint_a: &Pubkey,
     mint_b: &Pubkey,
+    seed: u64,
 ) -> (Pubkey, u8) {
     Pubkey::find_program_address(
-        &[b"escrow", maker.as_ref(), mint_a.as_ref(), mint_b.as_ref()],
+        &[
+            b"escrow",
+            maker.as_ref(),
+            mint_a.as_ref(),
+            mint_b.as_ref(),
+            &seed.to_le_bytes(),
+        ],
         program_id,
     )
 }
@@ -53,3 +139,7 @@ pub fn derive_escrow_pda(
 pub fn derive_vault_pda(escrow: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
     Pubkey::find_program_address(&[b"vault", escrow.as_ref()], program_id)
 }
+
+pub fn derive_config_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], program_id)
+}
@@ -0,0 +1,834 @@
+//! Checks `idl/escrow.json` against the program's actual Borsh encoding, so
+//! the IDL cannot drift from `EscrowInstructions`, the account layouts or
+//! `EscrowError`.
+
+use std::str::FromStr;
+
+use escrow::{
+    error::EscrowError,
+    instruction_builders::{
+        self, ApproveMilestoneAccounts, SettleAccounts, TakeAccounts, UpdateAccounts,
+    },
+    instructions::{EscrowInstructions, EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{
+        AccountType, Arbitration, Auction, BasketLeg, Config, Escrow, FeeMode, Milestone,
+        NativeSide, PaymentOption,
+    },
+};
+use serde_json::{json, Value};
+use solana_instruction::Instruction;
+use solana_program_error::ProgramError;
+use solana_pubkey::Pubkey;
+
+const IDL: &str = include_str!("../idl/escrow.json");
+
+fn idl() -> Value {
+    serde_json::from_str(IDL).unwrap()
+}
+
+fn find<'a>(items: &'a Value, name: &str) -> &'a Value {
+    items
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["name"] == name)
+        .unwrap_or_else(|| panic!("`{name}` missing from IDL"))
+}
+
+/// Borsh-encodes `value` as described by the IDL type `ty`.
+fn encode(idl: &Value, ty: &Value, value: &Value, out: &mut Vec<u8>) {
+    match ty {
+        Value::String(primitive) => match primitive.as_str() {
+            "bool" => out.push(value.as_bool().unwrap() as u8),
+            "u8" => out.push(value.as_u64().unwrap() as u8),
+            "u16" => out.extend_from_slice(&(value.as_u64().unwrap() as u16).to_le_bytes()),
+            "u64" => out.extend_from_slice(&value.as_u64().unwrap().to_le_bytes()),
+            "i64" => out.extend_from_slice(&value.as_i64().unwrap().to_le_bytes()),
+            "publicKey" => {
+                out.extend_from_slice(Pubkey::from_str(value.as_str().unwrap()).unwrap().as_ref())
+            }
+            "string" => {
+                let string = value.as_str().unwrap();
+                out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+                out.extend_from_slice(string.as_bytes());
+            }
+            other => panic!("unsupported IDL type {other}"),
+        },
+        Value::Object(ty) if ty.contains_key("option") => {
+            if value.is_null() {
+                out.push(0);
+            } else {
+                out.push(1);
+                encode(idl, &ty["option"], value, out);
+            }
+        }
+        Value::Object(ty) if ty.contains_key("vec") => {
+            let items = value.as_array().unwrap();
+            out.extend_from_slice(&(items.len() as u32).to_le_bytes());
+            for item in items {
+                encode(idl, &ty["vec"], item, out);
+            }
+        }
+        Value::Object(ty) if ty.contains_key("defined") => {
+            let defined = find(&idl["types"], ty["defined"].as_str().unwrap());
+            encode_defined(idl, &defined["type"], value, out);
+        }
+        other => panic!("unsupported IDL type {other}"),
+    }
+}
+
+fn encode_defined(idl: &Value, ty: &Value, value: &Value, out: &mut Vec<u8>) {
+    match ty["kind"].as_str().unwrap() {
+        "struct" => {
+            for field in ty["fields"].as_array().unwrap() {
+                let name = field["name"].as_str().unwrap();
+                encode(idl, &field["type"], &value[name], out);
+            }
+        }
+        // Unit variants are plain strings; others are `{ "Name": { fields } }`.
+        "enum" => {
+            let (name, fields) = match value {
+                Value::Object(variant) => {
+                    let (name, fields) = variant.iter().next().unwrap();
+                    (name.as_str(), fields)
+                }
+                name => (name.as_str().unwrap(), &Value::Null),
+            };
+            let variants = ty["variants"].as_array().unwrap();
+            let index = variants
+                .iter()
+                .position(|variant| variant["name"] == name)
+                .unwrap();
+            out.push(index as u8);
+            for field in variants[index]["fields"].as_array().into_iter().flatten() {
+                let field_name = field["name"].as_str().unwrap();
+                encode(idl, &field["type"], &fields[field_name], out);
+            }
+        }
+        other => panic!("unsupported IDL kind {other}"),
+    }
+}
+
+/// The largest Borsh encoding of `ty`, with every option present. Vectors
+/// have no bound in the IDL, so only their length prefix is counted.
+fn max_size(idl: &Value, ty: &Value) -> usize {
+    match ty {
+        Value::String(primitive) => match primitive.as_str() {
+            "bool" | "u8" => 1,
+            "u16" => 2,
+            "u64" | "i64" => 8,
+            "publicKey" => 32,
+            "string" => 4,
+            other => panic!("unsupported IDL type {other}"),
+        },
+        Value::Object(ty) if ty.contains_key("option") => 1 + max_size(idl, &ty["option"]),
+        Value::Object(ty) if ty.contains_key("vec") => 4,
+        Value::Object(ty) if ty.contains_key("defined") => {
+            let defined = &find(&idl["types"], ty["defined"].as_str().unwrap())["type"];
+            match defined["kind"].as_str().unwrap() {
+                "struct" => struct_size(idl, defined),
+                _ => 1,
+            }
+        }
+        other => panic!("unsupported IDL type {other}"),
+    }
+}
+
+fn struct_size(idl: &Value, ty: &Value) -> usize {
+    ty["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| max_size(idl, &field["type"]))
+        .sum()
+}
+
+fn encode_instruction(idl: &Value, name: &str, args: &Value) -> Vec<u8> {
+    let instruction = find(&idl["instructions"], name);
+    let mut out = vec![instruction["discriminant"]["value"].as_u64().unwrap() as u8];
+    for arg in instruction["args"].as_array().unwrap() {
+        let arg_name = arg["name"].as_str().unwrap();
+        encode(idl, &arg["type"], &args[arg_name], &mut out);
+    }
+    out
+}
+
+fn assert_accounts_match(idl: &Value, name: &str, ix: &Instruction) {
+    let accounts = find(&idl["instructions"], name)["accounts"]
+        .as_array()
+        .unwrap();
+    assert!(
+        accounts.len() >= ix.accounts.len(),
+        "{name} has more accounts than the IDL"
+    );
+    // Trailing optional accounts may be left out.
+    for account in &accounts[ix.accounts.len()..] {
+        assert_eq!(
+            account["isOptional"], true,
+            "{name}.{} omitted",
+            account["name"]
+        );
+    }
+    for (account, meta) in accounts.iter().zip(&ix.accounts) {
+        assert_eq!(
+            account["isMut"], meta.is_writable,
+            "{name}.{} isMut",
+            account["name"]
+        );
+        assert_eq!(
+            account["isSigner"], meta.is_signer,
+            "{name}.{} isSigner",
+            account["name"]
+        );
+    }
+}
+
+#[test]
+fn idl_instruction_data_matches_borsh() {
+    let idl = idl();
+    let allowed_taker = Pubkey::new_unique();
+
+    let make = EscrowInstructions::Make(MakeArgs {
+        seed: 7,
+        amount_offered: 100,
+        amount_required: 50,
+        expires_at: Some(1_700_000_000),
+        allowed_taker: Some(allowed_taker),
+        receive_fee_mode: FeeMode::Net,
+        basket: vec![30, 40],
+        kind: EscrowKind::Swap,
+    });
+    let make_args = json!({
+        "makeArgs": {
+            "seed": 7,
+            "amountOffered": 100,
+            "amountRequired": 50,
+            "expiresAt": 1_700_000_000,
+            "allowedTaker": allowed_taker.to_string(),
+            "receiveFeeMode": "Net",
+            "basket": [30, 40],
+            "kind": "Swap",
+        }
+    });
+    assert_eq!(
+        encode_instruction(&idl, "make", &make_args),
+        borsh::to_vec(&make).unwrap()
+    );
+
+    let (arbiter, seller) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let make = EscrowInstructions::Make(MakeArgs {
+        seed: 7,
+        amount_offered: 100,
+        amount_required: 0,
+        expires_at: None,
+        allowed_taker: None,
+        receive_fee_mode: FeeMode::Gross,
+        basket: Vec::new(),
+        kind: EscrowKind::Arbitrated { arbiter, seller },
+    });
+    let make_args = json!({
+        "makeArgs": {
+            "seed": 7,
+            "amountOffered": 100,
+            "amountRequired": 0,
+            "expiresAt": null,
+            "allowedTaker": null,
+            "receiveFeeMode": "Gross",
+            "basket": [],
+            "kind": {
+                "Arbitrated": { "arbiter": arbiter.to_string(), "seller": seller.to_string() }
+            },
+        }
+    });
+    assert_eq!(
+        encode_instruction(&idl, "make", &make_args),
+        borsh::to_vec(&make).unwrap()
+    );
+
+    assert_eq!(
+        encode_instruction(&idl, "take", &json!({ "amount": 25 })),
+        borsh::to_vec(&EscrowInstructions::Take { amount: 25 }).unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "refund", &json!({})),
+        borsh::to_vec(&EscrowInstructions::Refund).unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "closeExpired", &json!({})),
+        borsh::to_vec(&EscrowInstructions::CloseExpired).unwrap()
+    );
+
+    let treasury = Pubkey::new_unique();
+    assert_eq!(
+        encode_instruction(
+            &idl,
+            "initializeConfig",
+            &json!({
+                "feeBps": 250,
+                "treasury": treasury.to_string(),
+                "allowedTokenPrograms": [spl_token_interface::ID.to_string()],
+            })
+        ),
+        borsh::to_vec(&EscrowInstructions::InitializeConfig {
+            fee_bps: 250,
+            treasury,
+            allowed_token_programs: vec![spl_token_interface::ID],
+        })
+        .unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "updateFee", &json!({ "feeBps": 1000 })),
+        borsh::to_vec(&EscrowInstructions::UpdateFee { fee_bps: 1000 }).unwrap()
+    );
+    let update = EscrowInstructions::Update(UpdateArgs {
+        receive_amount: Some(40),
+        deposit: 0,
+        withdraw: 20,
+        expires_at: Some(Some(1_700_000_000)),
+    });
+    let update_args = json!({
+        "updateArgs": {
+            "receiveAmount": 40,
+            "deposit": 0,
+            "withdraw": 20,
+            "expiresAt": 1_700_000_000,
+        }
+    });
+    assert_eq!(
+        encode_instruction(&idl, "update", &update_args),
+        borsh::to_vec(&update).unwrap()
+    );
+
+    assert_eq!(
+        encode_instruction(&idl, "setPaymentMint", &json!({ "receiveAmount": 45 })),
+        borsh::to_vec(&EscrowInstructions::SetPaymentMint { receive_amount: 45 }).unwrap()
+    );
+    let auction = Auction {
+        start_price: 80,
+        end_price: 40,
+        start_at: 1_700_000_000,
+        end_at: 1_700_003_600,
+    };
+    let auction_args = json!({
+        "auction": {
+            "startPrice": 80,
+            "endPrice": 40,
+            "startAt": 1_700_000_000,
+            "endAt": 1_700_003_600,
+        }
+    });
+    assert_eq!(
+        encode_instruction(&idl, "setAuction", &auction_args),
+        borsh::to_vec(&EscrowInstructions::SetAuction {
+            auction: Some(auction)
+        })
+        .unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "setAuction", &json!({ "auction": null })),
+        borsh::to_vec(&EscrowInstructions::SetAuction { auction: None }).unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "settle", &json!({ "sellerAmount": 60 })),
+        borsh::to_vec(&EscrowInstructions::Settle { seller_amount: 60 }).unwrap()
+    );
+    let make_with_milestones = EscrowInstructions::Make(MakeArgs {
+        seed: 7,
+        amount_offered: 100,
+        amount_required: 0,
+        expires_at: None,
+        allowed_taker: None,
+        receive_fee_mode: FeeMode::Gross,
+        basket: Vec::new(),
+        kind: EscrowKind::Milestones {
+            beneficiary: treasury,
+            milestones: vec![
+                MilestoneArgs {
+                    name: "design".to_string(),
+                    amount: 30,
+                },
+                MilestoneArgs {
+                    name: "build".to_string(),
+                    amount: 70,
+                },
+            ],
+        },
+    });
+    let make_with_milestones_args = json!({
+        "makeArgs": {
+            "seed": 7,
+            "amountOffered": 100,
+            "amountRequired": 0,
+            "expiresAt": null,
+            "allowedTaker": null,
+            "receiveFeeMode": "Gross",
+            "basket": [],
+            "kind": {
+                "Milestones": {
+                    "beneficiary": treasury.to_string(),
+                    "milestones": [
+                        { "name": "design", "amount": 30 },
+                        { "name": "build", "amount": 70 },
+                    ],
+                }
+            },
+        }
+    });
+    assert_eq!(
+        encode_instruction(&idl, "make", &make_with_milestones_args),
+        borsh::to_vec(&make_with_milestones).unwrap()
+    );
+    assert_eq!(
+        encode_instruction(&idl, "approveMilestone", &json!({ "index": 1 })),
+        borsh::to_vec(&EscrowInstructions::ApproveMilestone { index: 1 }).unwrap()
+    );
+
+    assert_eq!(
+        encode_instruction(&idl, "setPaused", &json!({ "paused": true })),
+        borsh::to_vec(&EscrowInstructions::SetPaused { paused: true }).unwrap()
+    );
+    assert_eq!(
+        encode_instruction(
+            &idl,
+            "transferAdmin",
+            &json!({ "newAdmin": treasury.to_string() })
+        ),
+        borsh::to_vec(&EscrowInstructions::TransferAdmin {
+            new_admin: treasury
+        })
+        .unwrap()
+    );
+    assert_eq!(
+        encode_instruction(
+            &idl,
+            "setAllowedTokenPrograms",
+            &json!({ "allowedTokenPrograms": [treasury.to_string()] })
+        ),
+        borsh::to_vec(&EscrowInstructions::SetAllowedTokenPrograms {
+            allowed_token_programs: vec![treasury]
+        })
+        .unwrap()
+    );
+}
+
+#[test]
+fn idl_accounts_match_instruction_builders() {
+    let idl = idl();
+    let program_id = Pubkey::new_unique();
+    let maker = Pubkey::new_unique();
+    let mint_a = Pubkey::new_unique();
+    let mint_b = Pubkey::new_unique();
+    let token_program = spl_token_interface::ID;
+
+    let make = instruction_builders::make(
+        &program_id,
+        &maker,
+        &mint_a,
+        &mint_b,
+        &Pubkey::new_unique(),
+        &token_program,
+        MakeArgs {
+            seed: 7,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    assert_accounts_match(&idl, "make", &make);
+    let rent_payer = Pubkey::new_unique();
+    let make = instruction_builders::sponsor_rent(make, &rent_payer);
+    assert_accounts_match(&idl, "make", &make);
+
+    let take_accounts = TakeAccounts {
+        taker: Pubkey::new_unique(),
+        maker,
+        mint_a,
+        mint_b,
+        taker_token_a: Pubkey::new_unique(),
+        taker_token_b: Pubkey::new_unique(),
+        maker_token_b: Pubkey::new_unique(),
+        treasury_token_b: Pubkey::new_unique(),
+        token_program_a: token_program,
+        token_program_b: token_program,
+    };
+    let take = instruction_builders::take(&program_id, &take_accounts, 7, 50);
+    assert_accounts_match(&idl, "take", &take);
+    let take = instruction_builders::return_rent_to(take, &rent_payer);
+    assert_accounts_match(&idl, "take", &take);
+    let take = instruction_builders::take_paying_with(
+        &program_id,
+        &take_accounts,
+        &Pubkey::new_unique(),
+        7,
+        50,
+    );
+    assert_accounts_match(&idl, "take", &take);
+
+    let maker_token_a = Pubkey::new_unique();
+    let update = instruction_builders::update(
+        &program_id,
+        &UpdateAccounts {
+            maker,
+            mint_a,
+            mint_b,
+            maker_token_a,
+            token_program,
+        },
+        7,
+        UpdateArgs {
+            receive_amount: None,
+            deposit: 10,
+            withdraw: 0,
+            expires_at: None,
+        },
+    );
+    assert_accounts_match(&idl, "update", &update);
+
+    let (escrow, _) =
+        instruction_builders::find_escrow_address(&program_id, &maker, &mint_a, &mint_b, 7);
+
+    let set_payment_mint = instruction_builders::set_payment_mint(
+        &program_id,
+        &maker,
+        &escrow,
+        &Pubkey::new_unique(),
+        45,
+    );
+    assert_accounts_match(&idl, "setPaymentMint", &set_payment_mint);
+
+    let set_auction = instruction_builders::set_auction(&program_id, &maker, &escrow, None);
+    assert_accounts_match(&idl, "setAuction", &set_auction);
+
+    let settle = instruction_builders::settle(
+        &program_id,
+        &SettleAccounts {
+            signer: Pubkey::new_unique(),
+            maker,
+            mint_a,
+            seller_token_a: Pubkey::new_unique(),
+            maker_token_a,
+            token_program,
+        },
+        7,
+        60,
+    );
+    assert_accounts_match(&idl, "settle", &settle);
+    let settle = instruction_builders::return_rent_to(settle, &rent_payer);
+    assert_accounts_match(&idl, "settle", &settle);
+
+    let approve_milestone = instruction_builders::approve_milestone(
+        &program_id,
+        &ApproveMilestoneAccounts {
+            maker,
+            mint_a,
+            beneficiary_token_a: Pubkey::new_unique(),
+            token_program,
+        },
+        7,
+        0,
+    );
+    assert_accounts_match(&idl, "approveMilestone", &approve_milestone);
+    let approve_milestone = instruction_builders::return_rent_to(approve_milestone, &rent_payer);
+    assert_accounts_match(&idl, "approveMilestone", &approve_milestone);
+
+    let refund = instruction_builders::refund(
+        &program_id,
+        &maker,
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        7,
+    );
+    assert_accounts_match(&idl, "refund", &refund);
+    let refund = instruction_builders::return_rent_to(refund, &rent_payer);
+    assert_accounts_match(&idl, "refund", &refund);
+
+    let close_expired = instruction_builders::close_expired(
+        &program_id,
+        &maker,
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        7,
+    );
+    assert_accounts_match(&idl, "closeExpired", &close_expired);
+    let close_expired = instruction_builders::return_rent_to(close_expired, &rent_payer);
+    assert_accounts_match(&idl, "closeExpired", &close_expired);
+
+    let admin = Pubkey::new_unique();
+    let initialize_config = instruction_builders::initialize_config(
+        &program_id,
+        &admin,
+        250,
+        &Pubkey::new_unique(),
+        vec![token_program],
+    );
+    assert_accounts_match(&idl, "initializeConfig", &initialize_config);
+
+    let update_fee = instruction_builders::update_fee(&program_id, &admin, 250);
+    assert_accounts_match(&idl, "updateFee", &update_fee);
+
+    let set_paused = instruction_builders::set_paused(&program_id, &admin, true);
+    assert_accounts_match(&idl, "setPaused", &set_paused);
+
+    let transfer_admin =
+        instruction_builders::transfer_admin(&program_id, &admin, &Pubkey::new_unique());
+    assert_accounts_match(&idl, "transferAdmin", &transfer_admin);
+
+    let set_allowed_token_programs =
+        instruction_builders::set_allowed_token_programs(&program_id, &admin, vec![token_program]);
+    assert_accounts_match(&idl, "setAllowedTokenPrograms", &set_allowed_token_programs);
+}
+
+#[test]
+fn idl_escrow_layout_matches_borsh() {
+    let idl = idl();
+    let escrow_type = &find(&idl["accounts"], "Escrow")["type"];
+
+    let escrow = Escrow {
+        account_type: AccountType::Escrow,
+        version: Escrow::VERSION,
+        owner: Pubkey::new_unique(),
+        rent_payer: Pubkey::new_unique(),
+        seed: 7,
+        mint_a: Pubkey::new_unique(),
+        mint_b: Pubkey::new_unique(),
+        amount: 100,
+        receive_amount: 50,
+        expires_at: Some(1_700_000_000),
+        allowed_taker: Some(Pubkey::new_unique()),
+        receive_fee_mode: FeeMode::Net,
+        native_side: NativeSide::MintB,
+        bump: 254,
+        vault_bump: 253,
+        basket: vec![BasketLeg {
+            mint: Pubkey::new_unique(),
+            amount: 30,
+            vault_bump: 252,
+        }],
+        payment_options: vec![PaymentOption {
+            mint: Pubkey::new_unique(),
+            receive_amount: 45,
+        }],
+        auction: Some(Auction {
+            start_price: 80,
+            end_price: 40,
+            start_at: 1_700_000_000,
+            end_at: 1_700_003_600,
+        }),
+        arbitration: Some(Arbitration {
+            arbiter: Pubkey::new_unique(),
+            seller: Pubkey::new_unique(),
+        }),
+        beneficiary: Some(Pubkey::new_unique()),
+        milestones: vec![Milestone {
+            name: "design".to_string(),
+            amount: 30,
+            released: true,
+        }],
+    };
+    let value = json!({
+        "accountType": "Escrow",
+        "version": Escrow::VERSION,
+        "owner": escrow.owner.to_string(),
+        "rentPayer": escrow.rent_payer.to_string(),
+        "seed": 7,
+        "mintA": escrow.mint_a.to_string(),
+        "mintB": escrow.mint_b.to_string(),
+        "amount": 100,
+        "receiveAmount": 50,
+        "expiresAt": 1_700_000_000,
+        "allowedTaker": escrow.allowed_taker.unwrap().to_string(),
+        "receiveFeeMode": "Net",
+        "nativeSide": "MintB",
+        "bump": 254,
+        "vaultBump": 253,
+        "basket": [{
+            "mint": escrow.basket[0].mint.to_string(),
+            "amount": 30,
+            "vaultBump": 252,
+        }],
+        "paymentOptions": [{
+            "mint": escrow.payment_options[0].mint.to_string(),
+            "receiveAmount": 45,
+        }],
+        "auction": {
+            "startPrice": 80,
+            "endPrice": 40,
+            "startAt": 1_700_000_000,
+            "endAt": 1_700_003_600,
+        },
+        "arbitration": {
+            "arbiter": escrow.arbitration.unwrap().arbiter.to_string(),
+            "seller": escrow.arbitration.unwrap().seller.to_string(),
+        },
+        "beneficiary": escrow.beneficiary.unwrap().to_string(),
+        "milestones": [{
+            "name": "design",
+            "amount": 30,
+            "released": true,
+        }],
+    });
+
+    let mut out = Vec::new();
+    encode_defined(&idl, escrow_type, &value, &mut out);
+    assert_eq!(out, borsh::to_vec(&escrow).unwrap());
+    assert_eq!(
+        struct_size(&idl, escrow_type)
+            + BasketLeg::LEN * escrow.basket.len()
+            + PaymentOption::LEN * escrow.payment_options.len()
+            + Milestone::LEN * escrow.milestones.len(),
+        escrow.space()
+    );
+    assert_eq!(
+        max_size(&idl, &json!({ "defined": "BasketLeg" })),
+        BasketLeg::LEN
+    );
+    assert_eq!(
+        max_size(&idl, &json!({ "defined": "PaymentOption" })),
+        PaymentOption::LEN
+    );
+    assert_eq!(
+        max_size(&idl, &json!({ "defined": "Auction" })),
+        Auction::LEN
+    );
+    assert_eq!(
+        max_size(&idl, &json!({ "defined": "Arbitration" })),
+        Arbitration::LEN
+    );
+    assert_eq!(
+        max_size(&idl, &json!({ "defined": "Milestone" })) + Milestone::MAX_NAME_LEN,
+        Milestone::LEN
+    );
+}
+
+#[test]
+fn idl_config_layout_matches_borsh() {
+    let idl = idl();
+    let config_type = &find(&idl["accounts"], "Config")["type"];
+
+    let config = Config {
+        account_type: AccountType::Config,
+        version: Config::VERSION,
+        admin: Pubkey::new_unique(),
+        treasury: Pubkey::new_unique(),
+        fee_bps: 250,
+        paused: true,
+        bump: 254,
+        allowed_token_programs: vec![spl_token_interface::ID, spl_token_2022_interface::ID],
+    };
+    let value = json!({
+        "accountType": "Config",
+        "version": Config::VERSION,
+        "admin": config.admin.to_string(),
+        "treasury": config.treasury.to_string(),
+        "feeBps": 250,
+        "paused": true,
+        "bump": 254,
+        "allowedTokenPrograms": [
+            spl_token_interface::ID.to_string(),
+            spl_token_2022_interface::ID.to_string(),
+        ],
+    });
+
+    let mut out = Vec::new();
+    encode_defined(&idl, config_type, &value, &mut out);
+    assert_eq!(out, borsh::to_vec(&config).unwrap());
+    assert_eq!(
+        struct_size(&idl, config_type) + 32 * Config::MAX_TOKEN_PROGRAMS,
+        Config::LEN
+    );
+}
+
+/// Every `EscrowError` variant, in code order.
+fn all_errors() -> Vec<EscrowError> {
+    vec![
+        EscrowError::InvalidAmount,
+        EscrowError::InvalidMint,
+        EscrowError::InvalidUser,
+        EscrowError::EscrowExpired,
+        EscrowError::EscrowNotExpired,
+        EscrowError::TakerNotAllowed,
+        EscrowError::InvalidAccountType,
+        EscrowError::UnsupportedVersion,
+        EscrowError::InvalidEscrowOwner,
+        EscrowError::InvalidEscrowAddress,
+        EscrowError::MakerMismatch,
+        EscrowError::MintMismatch,
+        EscrowError::InvalidVault,
+        EscrowError::InvalidInstruction,
+        EscrowError::MissingSignature,
+        EscrowError::InvalidSystemProgram,
+        EscrowError::InvalidTokenProgram,
+        EscrowError::InvalidExpiry,
+        EscrowError::SameMint,
+        EscrowError::InvalidNativeAccount,
+        EscrowError::InvalidTokenAccount,
+        EscrowError::InsufficientFunds,
+        EscrowError::EscrowAlreadyExists,
+        EscrowError::AmountExceedsRemaining,
+        EscrowError::FillTooSmall,
+        EscrowError::Overflow,
+        EscrowError::Unauthorized,
+        EscrowError::FeeTooHigh,
+        EscrowError::InvalidConfig,
+        EscrowError::InvalidTreasury,
+        EscrowError::ConfigAlreadyExists,
+        EscrowError::ProgramPaused,
+        EscrowError::TokenProgramNotAllowed,
+        EscrowError::TooManyTokenPrograms,
+        EscrowError::InvalidRentPayer,
+        EscrowError::InvalidAssociatedTokenProgram,
+        EscrowError::InvalidBasketMint,
+        EscrowError::BasketFull,
+        EscrowError::PartialFillNotAllowed,
+        EscrowError::InvalidPaymentMint,
+        EscrowError::TooManyPaymentMints,
+        EscrowError::InvalidAuction,
+        EscrowError::Arbitrated,
+        EscrowError::InvalidArbitration,
+        EscrowError::InvalidRuling,
+        EscrowError::HasMilestones,
+        EscrowError::InvalidMilestones,
+        EscrowError::TooManyMilestones,
+        EscrowError::MilestoneUnavailable,
+        EscrowError::InvalidProgramData,
+        EscrowError::NotUpgradeAuthority,
+        EscrowError::NoTokenPrograms,
+        EscrowError::AuctionActive,
+    ]
+}
+
+#[test]
+fn idl_errors_match_escrow_error() {
+    let idl = idl();
+    let errors = all_errors();
+
+    let idl_errors = idl["errors"].as_array().unwrap();
+    assert_eq!(idl_errors.len(), errors.len());
+    for (idl_error, &error) in idl_errors.iter().zip(&errors) {
+        assert_eq!(idl_error["code"], error as u32);
+        assert_eq!(idl_error["name"], format!("{error:?}"));
+        assert_eq!(idl_error["msg"], error.to_string());
+    }
+}
+
+#[test]
+fn escrow_errors_decode_from_program_error() {
+    for error in all_errors() {
+        let program_error = ProgramError::from(error);
+        assert_eq!(EscrowError::decode(&program_error), Some(error));
+        assert_eq!(program_error.to_str::<EscrowError>(), error.to_string());
+    }
+    assert_eq!(
+        EscrowError::decode(&ProgramError::Custom(all_errors().len() as u32)),
+        None
+    );
+    assert_eq!(EscrowError::decode(&ProgramError::InvalidArgument), None);
+}
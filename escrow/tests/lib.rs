@@ -1,20 +1,37 @@
-use borsh::BorshDeserialize;
 use litesvm::LiteSVM;
 
-use escrow::state::Escrow;
+use escrow::{
+    error::EscrowError,
+    events::{EscrowEvent, EVENT_TAG},
+    instruction_builders::{
+        self, ApproveMilestoneAccounts, BasketLegAccounts, SettleAccounts, TakeAccounts,
+        UpdateAccounts,
+    },
+    instructions::{EscrowKind, MakeArgs, MilestoneArgs, UpdateArgs},
+    state::{
+        AccountType, Arbitration, Auction, BasketLeg, Config, Escrow, FeeMode, Milestone,
+        NativeSide, PaymentOption,
+    },
+};
 
-use solana_instruction::{AccountMeta, Instruction};
+use solana_account::Account;
+use solana_instruction::{error::InstructionError, AccountMeta, Instruction};
 use solana_keypair::Keypair;
+use solana_loader_v3_interface::{get_program_data_address, state::UpgradeableLoaderState};
 use solana_program_pack::Pack;
 use solana_pubkey::{pubkey, Pubkey};
 use solana_signer::Signer;
+use solana_sysvar::clock::Clock;
 use solana_transaction::Transaction;
+use solana_transaction_error::TransactionError;
+use spl_associated_token_account_interface::address::get_associated_token_address_with_program_id;
 use spl_token_interface::state::Account as TokenAccount;
 
 mod helpers;
 use helpers::*;
 
 const PROGRAM_ID: Pubkey = pubkey!("DinjxyZz2tjZTVi5FbKNoi2aayH71Q3EMzEh8yiGJnVY");
+const SEED: u64 = 42;
 
 fn setup_escrow() -> (
     LiteSVM,
@@ -42,20 +59,60 @@ fn setup_escrow() -> (
     svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
     svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
 
-    let mint_a = create_mint(&mut svm, 6, &maker);
-    let mint_b = create_mint(&mut svm, 6, &maker);
+    let mint_a = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+    let mint_b = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
 
-    let maker_token_a = create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_a);
-    let maker_token_b = create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_b);
+    let maker_token_a = create_token_account(
+        &mut svm,
+        &maker,
+        &maker.pubkey(),
+        &mint_a,
+        &spl_token_interface::ID,
+    );
+    let maker_token_b = create_token_account(
+        &mut svm,
+        &maker,
+        &maker.pubkey(),
+        &mint_b,
+        &spl_token_interface::ID,
+    );
 
-    let taker_token_a = create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_a);
-    let taker_token_b = create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_b);
+    let taker_token_a = create_token_account(
+        &mut svm,
+        &taker,
+        &taker.pubkey(),
+        &mint_a,
+        &spl_token_interface::ID,
+    );
+    let taker_token_b = create_token_account(
+        &mut svm,
+        &taker,
+        &taker.pubkey(),
+        &mint_b,
+        &spl_token_interface::ID,
+    );
 
-    mint_tokens(&mut svm, &maker, &mint_a, &maker, &maker_token_a, 100);
-    mint_tokens(&mut svm, &taker, &mint_b, &maker, &taker_token_b, 50);
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &spl_token_interface::ID,
+    );
+    mint_tokens(
+        &mut svm,
+        &taker,
+        &mint_b,
+        &maker,
+        &taker_token_b,
+        50,
+        &spl_token_interface::ID,
+    );
 
     let (escrow_pda, escrow_bump) =
-        derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b);
+        derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED);
 
     let (vault_pda, vault_bump) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
 
@@ -95,8 +152,14 @@ fn make() {
 
     // // Borsh 1.x uses 1-byte enum discriminant
     let mut instruction_data = vec![0u8];
+    instruction_data.extend_from_slice(&SEED.to_le_bytes());
     instruction_data.extend_from_slice(&amount_offered.to_le_bytes());
     instruction_data.extend_from_slice(&amount_required.to_le_bytes());
+    instruction_data.push(0); // expires_at: None
+    instruction_data.push(0); // allowed_taker: None
+    instruction_data.push(0); // receive_fee_mode: Gross
+    instruction_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    instruction_data.push(0); // kind: Swap
 
     let ix = Instruction {
         program_id: PROGRAM_ID,
@@ -110,6 +173,7 @@ fn make() {
             AccountMeta::new_readonly(spl_token_interface::ID, false),
             AccountMeta::new_readonly(solana_system_interface::program::ID, false),
             AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
         ],
         data: instruction_data,
     };
@@ -137,14 +201,80 @@ fn make() {
     assert_eq!(maker_token_amount.amount, 0);
 
     let escrow_account = svm.get_account(&escrow_pda).unwrap();
-    let escrow = Escrow::try_from_slice(&escrow_account.data).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
     assert_eq!(escrow.amount, 100);
     assert_eq!(escrow.receive_amount, 50);
     assert_eq!(escrow.owner, maker.pubkey());
+    assert_eq!(escrow.seed, SEED);
     assert_eq!(escrow.mint_a, mint_a);
     assert_eq!(escrow.mint_b, mint_b);
 }
 
+#[test]
+fn make_multiple_seeds() {
+    let (
+        mut svm,
+        maker,
+        _taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        _maker_token_b,
+        _taker_token_a,
+        _taker_token_b,
+        _escrow,
+        _vault,
+    ) = setup_escrow();
+
+    // Two offers on the same pair at different prices.
+    for (seed, amount_offered, amount_required) in [(1u64, 40u64, 20u64), (2, 60, 40)] {
+        let (escrow_pda, _) =
+            derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, seed);
+        let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+        let mut make_data = vec![0u8]; // discriminator for make fn
+        make_data.extend_from_slice(&seed.to_le_bytes());
+        make_data.extend_from_slice(&amount_offered.to_le_bytes());
+        make_data.extend_from_slice(&amount_required.to_le_bytes());
+        make_data.push(0); // expires_at: None
+        make_data.push(0); // allowed_taker: None
+        make_data.push(0); // receive_fee_mode: Gross
+        make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+        make_data.push(0); // kind: Swap
+
+        let make_ix = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(maker.pubkey(), true),
+                AccountMeta::new_readonly(mint_a, false),
+                AccountMeta::new_readonly(mint_b, false),
+                AccountMeta::new(maker_token_a, false),
+                AccountMeta::new(escrow_pda, false),
+                AccountMeta::new(vault_pda, false),
+                AccountMeta::new_readonly(spl_token_interface::ID, false),
+                AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+                AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+                AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            ],
+            data: make_data,
+        };
+
+        let tx = Transaction::new_signed_with_payer(
+            &[make_ix],
+            Some(&maker.pubkey()),
+            &[&maker],
+            svm.latest_blockhash(),
+        );
+        svm.send_transaction(tx).expect("Make instruction failed");
+
+        let escrow_account = svm.get_account(&escrow_pda).unwrap();
+        let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+        assert_eq!(escrow.seed, seed);
+        assert_eq!(escrow.amount, amount_offered);
+        assert_eq!(escrow.receive_amount, amount_required);
+    }
+}
+
 #[test]
 fn take() {
     let (
@@ -166,8 +296,14 @@ fn take() {
 
     // First execute make instruction to create the escrow
     let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
     make_data.extend_from_slice(&amount_offered.to_le_bytes());
     make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
 
     let make_ix = Instruction {
         program_id: PROGRAM_ID,
@@ -181,6 +317,7 @@ fn take() {
             AccountMeta::new_readonly(spl_token_interface::ID, false),
             AccountMeta::new_readonly(solana_system_interface::program::ID, false),
             AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
         ],
         data: make_data,
     };
@@ -208,6 +345,10 @@ fn take() {
             AccountMeta::new(vault_pda, false),
             AccountMeta::new_readonly(solana_system_interface::program::ID, false),
             AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
         ],
         data: instruction_data,
     };
@@ -244,6 +385,146 @@ fn take() {
     assert_eq!(escrow_balance, 0);
 }
 
+#[test]
+fn partial_take() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        (vault_pda, _vault_bump),
+    ) = setup_escrow();
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let blockhash = svm.latest_blockhash();
+    let tx =
+        Transaction::new_signed_with_payer(&[make_ix], Some(&maker.pubkey()), &[&maker], blockhash);
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let take_ix = |amount: u64| {
+        let mut instruction_data = vec![1u8]; // discriminator for take fn
+        instruction_data.extend_from_slice(&amount.to_le_bytes());
+
+        Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(taker.pubkey(), true),
+                AccountMeta::new(maker.pubkey(), false),
+                AccountMeta::new(mint_a, false),
+                AccountMeta::new(mint_b, false),
+                AccountMeta::new(taker_token_a, false),
+                AccountMeta::new(taker_token_b, false),
+                AccountMeta::new(maker_token_b, false),
+                AccountMeta::new(escrow_pda, false),
+                AccountMeta::new(vault_pda, false),
+                AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+                AccountMeta::new_readonly(spl_token_interface::ID, false),
+                AccountMeta::new_readonly(spl_token_interface::ID, false),
+                AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+                AccountMeta::new(maker_token_b, false),
+                AccountMeta::new_readonly(
+                    spl_associated_token_account_interface::program::ID,
+                    false,
+                ),
+            ],
+            data: instruction_data,
+        }
+    };
+
+    // Fill 20 of the 50 required: 40% of mint A is released.
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix(20)],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Partial take ix failed");
+
+    let taker_token_a_account = svm.get_account(&taker_token_a).unwrap();
+    let taker_token_a_data = TokenAccount::unpack(&taker_token_a_account.data).unwrap();
+    assert_eq!(taker_token_a_data.amount, 40);
+
+    let vault = svm.get_account(&vault_pda).unwrap();
+    let vault_token = TokenAccount::unpack(&vault.data).unwrap();
+    assert_eq!(vault_token.amount, 60);
+
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow.amount, 60);
+    assert_eq!(escrow.receive_amount, 30);
+
+    // Overfilling the remainder is rejected.
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix(31)],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let err = svm.send_transaction(tx).unwrap_err();
+    assert_eq!(
+        err.err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(EscrowError::AmountExceedsRemaining as u32)
+        )
+    );
+
+    // Filling the remainder closes the escrow.
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix(30)],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Final take ix failed");
+
+    let taker_token_a_account = svm.get_account(&taker_token_a).unwrap();
+    let taker_token_a_data = TokenAccount::unpack(&taker_token_a_account.data).unwrap();
+    assert_eq!(taker_token_a_data.amount, 100);
+
+    let maker_token_b_account = svm.get_account(&maker_token_b).unwrap();
+    let maker_token_b_data = TokenAccount::unpack(&maker_token_b_account.data).unwrap();
+    assert_eq!(maker_token_b_data.amount, amount_required);
+
+    let escrow_balance = svm.get_balance(&escrow_pda).unwrap_or(0);
+    assert_eq!(escrow_balance, 0);
+}
+
 #[test]
 fn refund() {
     let (
@@ -265,8 +546,14 @@ fn refund() {
 
     // First execute make instruction to create the escrow
     let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
     make_data.extend_from_slice(&amount_offered.to_le_bytes());
     make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
 
     let make_ix = Instruction {
         program_id: PROGRAM_ID,
@@ -280,6 +567,7 @@ fn refund() {
             AccountMeta::new_readonly(spl_token_interface::ID, false),
             AccountMeta::new_readonly(solana_system_interface::program::ID, false),
             AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
         ],
         data: make_data,
     };
@@ -320,3 +608,3253 @@ fn refund() {
     let escrow_balance = svm.get_balance(&escrow_pda).unwrap_or(0);
     assert_eq!(escrow_balance, 0);
 }
+
+#[test]
+fn close_expired() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        (vault_pda, _vault_bump),
+    ) = setup_escrow();
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let mut clock = svm.get_sysvar::<Clock>();
+    clock.unix_timestamp = 1_000;
+    svm.set_sysvar(&clock);
+
+    let expires_at: i64 = 2_000;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(1); // expires_at: Some
+    make_data.extend_from_slice(&expires_at.to_le_bytes());
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let blockhash = svm.latest_blockhash();
+    let tx =
+        Transaction::new_signed_with_payer(&[make_ix], Some(&maker.pubkey()), &[&maker], blockhash);
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow.expires_at, Some(expires_at));
+
+    // Anyone can crank, here the taker pays for the transaction.
+    let close_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+        ],
+        data: vec![3u8], // discriminator for close_expired fn
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        std::slice::from_ref(&close_ix),
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let err = svm.send_transaction(tx).unwrap_err();
+    assert_eq!(
+        err.err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(EscrowError::EscrowNotExpired as u32)
+        ),
+        "Escrow closed before expiry"
+    );
+
+    clock.unix_timestamp = expires_at;
+    svm.set_sysvar(&clock);
+
+    // Fills are rejected once the escrow has expired.
+    let mut take_data = vec![1u8]; // discriminator for take fn
+    take_data.extend_from_slice(&amount_required.to_le_bytes());
+
+    let take_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(mint_a, false),
+            AccountMeta::new(mint_b, false),
+            AccountMeta::new(taker_token_a, false),
+            AccountMeta::new(taker_token_b, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
+        ],
+        data: take_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let err = svm.send_transaction(tx).unwrap_err();
+    assert_eq!(
+        err.err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(EscrowError::EscrowExpired as u32)
+        ),
+        "Expired escrow was taken"
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[close_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("CloseExpired ix failed");
+
+    let maker_token_a_account = svm.get_account(&maker_token_a).unwrap();
+    let maker_token_data = TokenAccount::unpack(&maker_token_a_account.data).unwrap();
+    assert_eq!(maker_token_data.amount, amount_offered);
+
+    let escrow_balance = svm.get_balance(&escrow_pda).unwrap_or(0);
+    assert_eq!(escrow_balance, 0);
+}
+
+#[test]
+fn take_designated_taker() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        (vault_pda, _vault_bump),
+    ) = setup_escrow();
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let stranger = Keypair::new();
+    svm.airdrop(&stranger.pubkey(), 100_000_000_000).unwrap();
+    let stranger_token_a = create_token_account(
+        &mut svm,
+        &stranger,
+        &stranger.pubkey(),
+        &mint_a,
+        &spl_token_interface::ID,
+    );
+    let stranger_token_b = create_token_account(
+        &mut svm,
+        &stranger,
+        &stranger.pubkey(),
+        &mint_b,
+        &spl_token_interface::ID,
+    );
+    mint_tokens(
+        &mut svm,
+        &stranger,
+        &mint_b,
+        &maker,
+        &stranger_token_b,
+        50,
+        &spl_token_interface::ID,
+    );
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(1); // allowed_taker: Some
+    make_data.extend_from_slice(taker.pubkey().as_ref());
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let blockhash = svm.latest_blockhash();
+    let tx =
+        Transaction::new_signed_with_payer(&[make_ix], Some(&maker.pubkey()), &[&maker], blockhash);
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow.allowed_taker, Some(taker.pubkey()));
+
+    let take_ix = |taker: &Pubkey, taker_token_a: &Pubkey, taker_token_b: &Pubkey| {
+        let mut instruction_data = vec![1u8]; // discriminator for take fn
+        instruction_data.extend_from_slice(&amount_required.to_le_bytes());
+
+        Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(*taker, true),
+                AccountMeta::new(maker.pubkey(), false),
+                AccountMeta::new(mint_a, false),
+                AccountMeta::new(mint_b, false),
+                AccountMeta::new(*taker_token_a, false),
+                AccountMeta::new(*taker_token_b, false),
+                AccountMeta::new(maker_token_b, false),
+                AccountMeta::new(escrow_pda, false),
+                AccountMeta::new(vault_pda, false),
+                AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+                AccountMeta::new_readonly(spl_token_interface::ID, false),
+                AccountMeta::new_readonly(spl_token_interface::ID, false),
+                AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+                AccountMeta::new(maker_token_b, false),
+                AccountMeta::new_readonly(
+                    spl_associated_token_account_interface::program::ID,
+                    false,
+                ),
+            ],
+            data: instruction_data,
+        }
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix(
+            &stranger.pubkey(),
+            &stranger_token_a,
+            &stranger_token_b,
+        )],
+        Some(&stranger.pubkey()),
+        &[&stranger],
+        svm.latest_blockhash(),
+    );
+    let err = svm.send_transaction(tx).unwrap_err();
+    assert_eq!(
+        err.err,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(EscrowError::TakerNotAllowed as u32)
+        )
+    );
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix(&taker.pubkey(), &taker_token_a, &taker_token_b)],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Designated take ix failed");
+
+    let taker_token_a_account = svm.get_account(&taker_token_a).unwrap();
+    let taker_token_a_data = TokenAccount::unpack(&taker_token_a_account.data).unwrap();
+    assert_eq!(taker_token_a_data.amount, amount_offered);
+}
+
+#[test]
+fn make_take_token_2022() {
+    let mut svm = LiteSVM::new();
+
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+    svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
+
+    // Mint A lives under Token-2022, mint B under the legacy token program.
+    let token_program_a = spl_token_2022_interface::ID;
+    let token_program_b = spl_token_interface::ID;
+
+    let mint_a = create_mint(&mut svm, 6, &maker, &token_program_a);
+    let mint_b = create_mint(&mut svm, 6, &maker, &token_program_b);
+
+    let maker_token_a =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_a, &token_program_a);
+    let maker_token_b =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_b, &token_program_b);
+    let taker_token_a =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_a, &token_program_a);
+    let taker_token_b =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_b, &token_program_b);
+
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &token_program_a,
+    );
+    mint_tokens(
+        &mut svm,
+        &taker,
+        &mint_b,
+        &maker,
+        &taker_token_b,
+        50,
+        &token_program_b,
+    );
+
+    let (escrow_pda, _) = derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(token_program_a, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[make_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let vault = svm.get_account(&vault_pda).unwrap();
+    assert_eq!(vault.owner, token_program_a);
+    assert_eq!(token_balance(&svm, &vault_pda), amount_offered);
+
+    let mut take_data = vec![1u8]; // discriminator for take fn
+    take_data.extend_from_slice(&amount_required.to_le_bytes());
+
+    let take_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(mint_a, false),
+            AccountMeta::new(mint_b, false),
+            AccountMeta::new(taker_token_a, false),
+            AccountMeta::new(taker_token_b, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(token_program_a, false),
+            AccountMeta::new_readonly(token_program_b, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
+        ],
+        data: take_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Take ix failed");
+
+    assert_eq!(token_balance(&svm, &taker_token_a), amount_offered);
+    assert_eq!(token_balance(&svm, &maker_token_b), amount_required);
+
+    let escrow_balance = svm.get_balance(&escrow_pda).unwrap_or(0);
+    assert_eq!(escrow_balance, 0);
+}
+
+#[test]
+fn make_take_transfer_fee() {
+    let mut svm = LiteSVM::new();
+
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+    svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
+
+    let token_program = spl_token_2022_interface::ID;
+
+    // 1% fee on mint A, 10% fee on mint B.
+    let mint_a = create_mint_with_transfer_fee(&mut svm, 6, &maker, 100, u64::MAX);
+    let mint_b = create_mint_with_transfer_fee(&mut svm, 6, &maker, 1_000, u64::MAX);
+
+    let maker_token_a =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_a, &token_program);
+    let maker_token_b =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_b, &token_program);
+    let taker_token_a =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_a, &token_program);
+    let taker_token_b =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_b, &token_program);
+
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &token_program,
+    );
+    mint_tokens(
+        &mut svm,
+        &taker,
+        &mint_b,
+        &maker,
+        &taker_token_b,
+        100,
+        &token_program,
+    );
+
+    let (escrow_pda, _) = derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(1); // receive_fee_mode: Net
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[make_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    // The escrow tracks what reached the vault, not what was requested.
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(token_balance(&svm, &vault_pda), 99);
+    assert_eq!(escrow.amount, 99);
+    assert_eq!(escrow.receive_fee_mode, FeeMode::Net);
+
+    let mut take_data = vec![1u8]; // discriminator for take fn
+    take_data.extend_from_slice(&amount_required.to_le_bytes());
+
+    let take_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(mint_a, false),
+            AccountMeta::new(mint_b, false),
+            AccountMeta::new(taker_token_a, false),
+            AccountMeta::new(taker_token_b, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            AccountMeta::new(maker_token_b, false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
+        ],
+        data: take_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Take ix failed");
+
+    // Net mode: the maker receives exactly what they asked for and the taker
+    // pays the 10% fee on top.
+    assert_eq!(token_balance(&svm, &maker_token_b), amount_required);
+    assert_eq!(token_balance(&svm, &taker_token_b), 100 - 56);
+    assert_eq!(token_balance(&svm, &taker_token_a), 98);
+    // The fee withheld in the vault by the make deposit is harvested to the
+    // mint, so the vault can still close.
+    assert!(svm
+        .get_account(&vault_pda)
+        .is_none_or(|vault| vault.lamports == 0));
+    assert!(svm
+        .get_account(&escrow_pda)
+        .is_none_or(|escrow| escrow.lamports == 0));
+
+    // So can a refunded one.
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &token_program,
+    );
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        MakeArgs {
+            seed: SEED + 1,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    send(&mut svm, make_ix, &maker).expect("Make instruction failed");
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        SEED + 1,
+    );
+    send(&mut svm, refund_ix, &maker).expect("Refund instruction failed");
+    // 1% is withheld on the way in and again on the way back.
+    assert_eq!(token_balance(&svm, &maker_token_a), 98);
+    let (escrow_pda, _) =
+        derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED + 1);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+    assert!(svm
+        .get_account(&vault_pda)
+        .is_none_or(|vault| vault.lamports == 0));
+    assert!(svm
+        .get_account(&escrow_pda)
+        .is_none_or(|escrow| escrow.lamports == 0));
+}
+
+#[test]
+fn make_take_native_sol() {
+    let mut svm = LiteSVM::new();
+
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+    svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
+
+    // Maker sells mint A tokens for native SOL.
+    let token_program = spl_token_interface::ID;
+    let mint_a = create_mint(&mut svm, 6, &maker, &token_program);
+    let mint_b = spl_token_interface::native_mint::ID;
+
+    let maker_token_a =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_a, &token_program);
+    let taker_token_a =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_a, &token_program);
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &token_program,
+    );
+
+    let (escrow_pda, _) = derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 2_000_000_000;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[make_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow.native_side, NativeSide::MintB);
+
+    let maker_lamports_before = svm.get_balance(&maker.pubkey()).unwrap();
+
+    let mut take_data = vec![1u8]; // discriminator for take fn
+    take_data.extend_from_slice(&amount_required.to_le_bytes());
+
+    // The taker's and maker's wallets stand in for their mint B token accounts,
+    // and the system program stands in for mint B's token program.
+    let take_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(taker_token_a, false),
+            AccountMeta::new(taker.pubkey(), true),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+            AccountMeta::new(maker.pubkey(), false),
+            AccountMeta::new_readonly(spl_associated_token_account_interface::program::ID, false),
+        ],
+        data: take_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Take ix failed");
+
+    assert_eq!(token_balance(&svm, &taker_token_a), amount_offered);
+
+    // The maker receives the SOL plus the rent of the closed escrow and vault.
+    let maker_lamports_after = svm.get_balance(&maker.pubkey()).unwrap();
+    assert!(maker_lamports_after >= maker_lamports_before + amount_required);
+
+    let escrow_balance = svm.get_balance(&escrow_pda).unwrap_or(0);
+    assert_eq!(escrow_balance, 0);
+}
+
+#[test]
+fn make_refund_native_sol() {
+    let mut svm = LiteSVM::new();
+
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+
+    // Maker offers native SOL for mint B tokens.
+    let mint_a = spl_token_interface::native_mint::ID;
+    let mint_b = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+
+    let (escrow_pda, _) = derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+    let amount_offered: u64 = 5_000_000_000;
+    let amount_required: u64 = 50;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[make_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let vault = svm.get_account(&vault_pda).unwrap();
+    assert_eq!(vault.owner, PROGRAM_ID);
+    assert!(vault.lamports >= amount_offered);
+
+    let escrow_account = svm.get_account(&escrow_pda).unwrap();
+    let escrow = Escrow::unpack(&escrow_account.data).unwrap();
+    assert_eq!(escrow.native_side, NativeSide::MintA);
+    assert_eq!(escrow.amount, amount_offered);
+
+    let maker_lamports_before = svm.get_balance(&maker.pubkey()).unwrap();
+
+    let refund_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+        ],
+        data: vec![2u8],
+    };
+
+    let tx = Transaction::new_signed_with_payer(
+        &[refund_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("Refund ix failed");
+
+    let maker_lamports_after = svm.get_balance(&maker.pubkey()).unwrap();
+    assert!(maker_lamports_after >= maker_lamports_before + amount_offered);
+    assert_eq!(svm.get_balance(&vault_pda).unwrap_or(0), 0);
+    assert_eq!(svm.get_balance(&escrow_pda).unwrap_or(0), 0);
+}
+
+#[test]
+fn escrow_space_matches_layout() {
+    let mut escrow = Escrow {
+        account_type: AccountType::Escrow,
+        version: Escrow::VERSION,
+        owner: Pubkey::new_unique(),
+        rent_payer: Pubkey::new_unique(),
+        seed: u64::MAX,
+        mint_a: Pubkey::new_unique(),
+        mint_b: Pubkey::new_unique(),
+        amount: u64::MAX,
+        receive_amount: u64::MAX,
+        expires_at: Some(i64::MAX),
+        allowed_taker: Some(Pubkey::new_unique()),
+        receive_fee_mode: FeeMode::Net,
+        native_side: NativeSide::MintB,
+        bump: u8::MAX,
+        vault_bump: u8::MAX,
+        basket: vec![
+            BasketLeg {
+                mint: Pubkey::new_unique(),
+                amount: u64::MAX,
+                vault_bump: u8::MAX,
+            };
+            Escrow::MAX_BASKET_LEGS
+        ],
+        payment_options: vec![
+            PaymentOption {
+                mint: Pubkey::new_unique(),
+                receive_amount: u64::MAX,
+            };
+            Escrow::MAX_PAYMENT_OPTIONS
+        ],
+        auction: Some(Auction {
+            start_price: u64::MAX,
+            end_price: u64::MAX,
+            start_at: i64::MAX,
+            end_at: i64::MAX,
+        }),
+        arbitration: Some(Arbitration {
+            arbiter: Pubkey::new_unique(),
+            seller: Pubkey::new_unique(),
+        }),
+        beneficiary: Some(Pubkey::new_unique()),
+        milestones: vec![
+            Milestone {
+                name: "m".repeat(Milestone::MAX_NAME_LEN),
+                amount: u64::MAX,
+                released: true,
+            };
+            Escrow::MAX_MILESTONES
+        ],
+    };
+
+    assert_eq!(borsh::to_vec(&escrow).unwrap().len(), escrow.space());
+
+    // A plain swap only keeps room for what can be set after make.
+    escrow.basket.clear();
+    escrow.payment_options.clear();
+    escrow.arbitration = None;
+    escrow.beneficiary = None;
+    escrow.milestones.clear();
+    assert_eq!(borsh::to_vec(&escrow).unwrap().len(), escrow.space());
+}
+
+#[test]
+fn refund_rejects_wrong_discriminator() {
+    let (
+        mut svm,
+        maker,
+        _taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        _maker_token_b,
+        _taker_token_a,
+        _taker_token_b,
+        (escrow_pda, _escrow_bump),
+        (vault_pda, _vault_bump),
+    ) = setup_escrow();
+
+    let amount_offered: u64 = 100;
+    let amount_required: u64 = 50;
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&amount_offered.to_le_bytes());
+    make_data.extend_from_slice(&amount_required.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+
+    let make_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new_readonly(mint_a, false),
+            AccountMeta::new_readonly(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+            AccountMeta::new_readonly(solana_system_interface::program::ID, false),
+            AccountMeta::new_readonly(solana_sysvar::rent::ID, false),
+            AccountMeta::new_readonly(derive_config_pda(&PROGRAM_ID).0, false),
+        ],
+        data: make_data,
+    };
+
+    let blockhash = svm.latest_blockhash();
+    let tx =
+        Transaction::new_signed_with_payer(&[make_ix], Some(&maker.pubkey()), &[&maker], blockhash);
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let refund_ix = Instruction {
+        program_id: PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(maker.pubkey(), true),
+            AccountMeta::new(mint_a, false),
+            AccountMeta::new(mint_b, false),
+            AccountMeta::new(maker_token_a, false),
+            AccountMeta::new(escrow_pda, false),
+            AccountMeta::new(vault_pda, false),
+            AccountMeta::new_readonly(spl_token_interface::ID, false),
+        ],
+        data: vec![2u8],
+    };
+
+    let original = svm.get_account(&escrow_pda).unwrap();
+
+    for (offset, byte, error) in [
+        (
+            0,
+            AccountType::Uninitialized as u8,
+            EscrowError::InvalidAccountType,
+        ),
+        (1, Escrow::VERSION + 1, EscrowError::UnsupportedVersion),
+    ] {
+        let mut tampered = original.clone();
+        tampered.data[offset] = byte;
+        svm.set_account(escrow_pda, tampered).unwrap();
+
+        let tx = Transaction::new_signed_with_payer(
+            std::slice::from_ref(&refund_ix),
+            Some(&maker.pubkey()),
+            &[&maker],
+            svm.latest_blockhash(),
+        );
+        let err = svm.send_transaction(tx).unwrap_err();
+        assert_eq!(
+            err.err,
+            TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+        );
+    }
+}
+
+/// Makes the default 100-for-50 escrow and returns take and refund
+/// instructions for it, so a test can swap one account and expect a rejection.
+fn setup_validation() -> (LiteSVM, Keypair, Keypair, Instruction, Instruction) {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        _escrow,
+        _vault,
+    ) = setup_escrow();
+
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+
+    let blockhash = svm.latest_blockhash();
+    let tx =
+        Transaction::new_signed_with_payer(&[make_ix], Some(&maker.pubkey()), &[&maker], blockhash);
+    svm.send_transaction(tx).expect("Make instruction failed");
+
+    let take_ix = instruction_builders::take(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker: taker.pubkey(),
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            taker_token_a,
+            taker_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            token_program_a: spl_token_interface::ID,
+            token_program_b: spl_token_interface::ID,
+        },
+        SEED,
+        50,
+    );
+
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        SEED,
+    );
+
+    (svm, maker, taker, take_ix, refund_ix)
+}
+
+fn assert_rejected(svm: &mut LiteSVM, ix: Instruction, signer: &Keypair, error: EscrowError) {
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&signer.pubkey()),
+        &[signer],
+        svm.latest_blockhash(),
+    );
+    let err = svm.send_transaction(tx).unwrap_err();
+    assert_eq!(
+        err.err,
+        TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+    );
+}
+
+#[test]
+fn take_refund_reject_escrow_not_owned_by_program() {
+    let (mut svm, maker, taker, take_ix, refund_ix) = setup_validation();
+    let escrow_pda = take_ix.accounts[7].pubkey;
+
+    // Same bytes, but held by an account the escrow program does not own.
+    let mut forged = svm.get_account(&escrow_pda).unwrap();
+    forged.owner = solana_system_interface::program::ID;
+    svm.set_account(escrow_pda, forged).unwrap();
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::InvalidEscrowOwner);
+    assert_rejected(&mut svm, refund_ix, &maker, EscrowError::InvalidEscrowOwner);
+}
+
+#[test]
+fn take_refund_reject_wrong_escrow_address() {
+    let (mut svm, maker, taker, mut take_ix, mut refund_ix) = setup_validation();
+    let escrow_pda = take_ix.accounts[7].pubkey;
+
+    // A program-owned copy of the escrow living at an address that is not the PDA.
+    let copy = Pubkey::new_unique();
+    svm.set_account(copy, svm.get_account(&escrow_pda).unwrap())
+        .unwrap();
+    take_ix.accounts[7].pubkey = copy;
+    refund_ix.accounts[4].pubkey = copy;
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::InvalidEscrowAddress);
+    assert_rejected(
+        &mut svm,
+        refund_ix,
+        &maker,
+        EscrowError::InvalidEscrowAddress,
+    );
+}
+
+#[test]
+fn take_refund_reject_wrong_maker() {
+    let (mut svm, _maker, taker, mut take_ix, mut refund_ix) = setup_validation();
+
+    take_ix.accounts[1].pubkey = taker.pubkey();
+    refund_ix.accounts[0].pubkey = taker.pubkey();
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::MakerMismatch);
+    assert_rejected(&mut svm, refund_ix, &taker, EscrowError::MakerMismatch);
+}
+
+#[test]
+fn take_refund_reject_wrong_mint() {
+    let (mut svm, maker, taker, mut take_ix, mut refund_ix) = setup_validation();
+
+    let other_mint = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+    take_ix.accounts[3].pubkey = other_mint;
+    refund_ix.accounts[2].pubkey = other_mint;
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::MintMismatch);
+    assert_rejected(&mut svm, refund_ix, &maker, EscrowError::MintMismatch);
+}
+
+#[test]
+fn take_refund_reject_wrong_mint_a() {
+    let (mut svm, maker, taker, mut take_ix, mut refund_ix) = setup_validation();
+
+    let other_mint = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+    take_ix.accounts[2].pubkey = other_mint;
+    refund_ix.accounts[1].pubkey = other_mint;
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::MintMismatch);
+    assert_rejected(&mut svm, refund_ix, &maker, EscrowError::MintMismatch);
+}
+
+#[test]
+fn take_rejects_wrong_maker_token_b() {
+    let (mut svm, maker, taker, take_ix, refund_ix) = setup_validation();
+    let taker_token_b = take_ix.accounts[5].pubkey;
+    let maker_token_a = refund_ix.accounts[3].pubkey;
+
+    // The taker's own mint B account, so the payment would come straight back.
+    let mut ix = take_ix.clone();
+    ix.accounts[6].pubkey = taker_token_b;
+    assert_rejected(&mut svm, ix, &taker, EscrowError::InvalidUser);
+
+    // The maker's account, but for mint A.
+    let mut ix = take_ix.clone();
+    ix.accounts[6].pubkey = maker_token_a;
+    assert_rejected(&mut svm, ix, &taker, EscrowError::InvalidMint);
+
+    // Not a token account at all.
+    let mut ix = take_ix;
+    ix.accounts[6].pubkey = maker.pubkey();
+    assert_rejected(&mut svm, ix, &taker, EscrowError::InvalidTokenAccount);
+}
+
+#[test]
+fn take_refund_reject_wrong_vault() {
+    let (mut svm, maker, taker, mut take_ix, mut refund_ix) = setup_validation();
+
+    let other_vault = Pubkey::new_unique();
+    take_ix.accounts[8].pubkey = other_vault;
+    refund_ix.accounts[5].pubkey = other_vault;
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::InvalidVault);
+    assert_rejected(&mut svm, refund_ix, &maker, EscrowError::InvalidVault);
+}
+
+#[test]
+fn take_refund_reject_tampered_bump() {
+    let (mut svm, maker, taker, take_ix, refund_ix) = setup_validation();
+    let escrow_pda = take_ix.accounts[7].pubkey;
+
+    let mut account = svm.get_account(&escrow_pda).unwrap();
+    let mut escrow = Escrow::unpack(&account.data).unwrap();
+    escrow.bump = escrow.bump.wrapping_sub(1);
+    let bytes = borsh::to_vec(&escrow).unwrap();
+    account.data[..bytes.len()].copy_from_slice(&bytes);
+    svm.set_account(escrow_pda, account).unwrap();
+
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::InvalidEscrowAddress);
+    assert_rejected(
+        &mut svm,
+        refund_ix,
+        &maker,
+        EscrowError::InvalidEscrowAddress,
+    );
+}
+
+/// Keeps take and refund within their compute budgets. The ceilings are
+/// loose bounds, not measurements: no SBF build was available to take a
+/// baseline, so run this with `--nocapture` against a built escrow.so and
+/// set them just above the reported numbers.
+#[test]
+fn compute_units() {
+    let (mut svm, _maker, taker, take_ix, _refund_ix) = setup_validation();
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let take_cu = svm
+        .send_transaction(tx)
+        .expect("Take instruction failed")
+        .compute_units_consumed;
+
+    let (mut svm, maker, _taker, _take_ix, refund_ix) = setup_validation();
+    let tx = Transaction::new_signed_with_payer(
+        &[refund_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    let refund_cu = svm
+        .send_transaction(tx)
+        .expect("Refund instruction failed")
+        .compute_units_consumed;
+
+    println!("take: {take_cu} CU, refund: {refund_cu} CU");
+    assert!(take_cu <= 50_000, "take used {take_cu} CU");
+    assert!(refund_cu <= 30_000, "refund used {refund_cu} CU");
+}
+
+#[test]
+fn instruction_builders_match_wire_format() {
+    let maker = Pubkey::new_unique();
+    let mint_a = Pubkey::new_unique();
+    let mint_b = Pubkey::new_unique();
+    let maker_token_a = Pubkey::new_unique();
+    let (escrow_pda, _) = derive_escrow_pda(&PROGRAM_ID, &maker, &mint_a, &mint_b, SEED);
+    let (vault_pda, _) = derive_vault_pda(&escrow_pda, &PROGRAM_ID);
+
+    let ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker,
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+
+    let mut make_data = vec![0u8]; // discriminator for make fn
+    make_data.extend_from_slice(&SEED.to_le_bytes());
+    make_data.extend_from_slice(&100u64.to_le_bytes());
+    make_data.extend_from_slice(&50u64.to_le_bytes());
+    make_data.push(0); // expires_at: None
+    make_data.push(0); // allowed_taker: None
+    make_data.push(0); // receive_fee_mode: Gross
+    make_data.extend_from_slice(&0u32.to_le_bytes()); // basket: empty
+    make_data.push(0); // kind: Swap
+    assert_eq!(ix.data, make_data);
+
+    let keys: Vec<Pubkey> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+    assert_eq!(
+        keys,
+        vec![
+            maker,
+            mint_a,
+            mint_b,
+            maker_token_a,
+            escrow_pda,
+            vault_pda,
+            spl_token_interface::ID,
+            solana_system_interface::program::ID,
+            solana_sysvar::rent::ID,
+            derive_config_pda(&PROGRAM_ID).0,
+        ]
+    );
+    assert!(ix.accounts[0].is_signer);
+
+    let ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker,
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        SEED,
+    );
+    assert_eq!(ix.data, vec![2u8]);
+    assert_eq!(ix.accounts[4].pubkey, escrow_pda);
+    assert_eq!(ix.accounts[5].pubkey, vault_pda);
+}
+
+fn escrow_events(logs: &[String]) -> Vec<EscrowEvent> {
+    logs.iter()
+        .filter_map(|line| EscrowEvent::from_log(line))
+        .collect()
+}
+
+#[test]
+fn lifecycle_events() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        _vault,
+    ) = setup_escrow();
+
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[make_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    let meta = svm.send_transaction(tx).expect("Make instruction failed");
+    assert_eq!(
+        escrow_events(&meta.logs),
+        vec![EscrowEvent::Created {
+            escrow: escrow_pda,
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            amount: 100,
+            receive_amount: 50,
+        }]
+    );
+
+    let take_ix = instruction_builders::take(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker: taker.pubkey(),
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            taker_token_a,
+            taker_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            token_program_a: spl_token_interface::ID,
+            token_program_b: spl_token_interface::ID,
+        },
+        SEED,
+        20,
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let meta = svm.send_transaction(tx).expect("Take instruction failed");
+    assert_eq!(
+        escrow_events(&meta.logs),
+        vec![EscrowEvent::Filled {
+            escrow: escrow_pda,
+            maker: maker.pubkey(),
+            taker: taker.pubkey(),
+            mint_a,
+            mint_b,
+            paid: 20,
+            protocol_fee: 0,
+            released: 40,
+            remaining: 30,
+        }]
+    );
+
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        SEED,
+    );
+    let tx = Transaction::new_signed_with_payer(
+        &[refund_ix],
+        Some(&maker.pubkey()),
+        &[&maker],
+        svm.latest_blockhash(),
+    );
+    let meta = svm.send_transaction(tx).expect("Refund instruction failed");
+    assert_eq!(
+        escrow_events(&meta.logs),
+        vec![EscrowEvent::Refunded {
+            escrow: escrow_pda,
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            amount: 60,
+        }]
+    );
+}
+
+#[test]
+fn event_decoder_ignores_foreign_data() {
+    let event = EscrowEvent::Refunded {
+        escrow: Pubkey::new_unique(),
+        maker: Pubkey::new_unique(),
+        mint_a: Pubkey::new_unique(),
+        mint_b: Pubkey::new_unique(),
+        amount: 5,
+    };
+    let data = borsh::to_vec(&event).unwrap();
+    assert_eq!(EscrowEvent::from_log_data(&[EVENT_TAG, &data]), Some(event));
+    assert_eq!(EscrowEvent::from_log_data(&[b"otherapp", &data]), None);
+    assert_eq!(EscrowEvent::from_log_data(&[&data]), None);
+    assert_eq!(
+        EscrowEvent::from_log("Program log: Instruction: Make"),
+        None
+    );
+}
+
+#[test]
+fn make_rejects_invalid_arguments() {
+    let (
+        mut svm,
+        maker,
+        _taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        _maker_token_b,
+        _taker_token_a,
+        _taker_token_b,
+        _escrow,
+        _vault,
+    ) = setup_escrow();
+
+    let now = svm.get_sysvar::<Clock>().unix_timestamp;
+    let args = |amount_offered, amount_required, expires_at| MakeArgs {
+        seed: SEED,
+        amount_offered,
+        amount_required,
+        expires_at,
+        allowed_taker: None,
+        receive_fee_mode: FeeMode::Gross,
+        basket: Vec::new(),
+        kind: EscrowKind::Swap,
+    };
+
+    for (mint_b, args, error) in [
+        (mint_b, args(0, 50, None), EscrowError::InvalidAmount),
+        (mint_b, args(100, 0, None), EscrowError::InvalidAmount),
+        (mint_b, args(100, 50, Some(now)), EscrowError::InvalidExpiry),
+        (mint_b, args(101, 50, None), EscrowError::InsufficientFunds),
+        (mint_a, args(100, 50, None), EscrowError::SameMint),
+    ] {
+        let ix = instruction_builders::make(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &mint_b,
+            &maker_token_a,
+            &spl_token_interface::ID,
+            args,
+        );
+        assert_rejected(&mut svm, ix, &maker, error);
+    }
+
+    // The token program passed for mint A must be the one that owns it.
+    let ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_2022_interface::ID,
+        args(100, 50, None),
+    );
+    assert_rejected(&mut svm, ix, &maker, EscrowError::InvalidTokenProgram);
+}
+
+#[test]
+fn take_rejects_invalid_amounts() {
+    let (mut svm, _maker, taker, mut take_ix, _refund_ix) = setup_validation();
+
+    for (amount, error) in [
+        (0u64, EscrowError::InvalidAmount),
+        (51, EscrowError::AmountExceedsRemaining),
+    ] {
+        let mut data = vec![1u8]; // discriminator for take fn
+        data.extend_from_slice(&amount.to_le_bytes());
+        take_ix.data = data;
+        assert_rejected(&mut svm, take_ix.clone(), &taker, error);
+    }
+}
+
+fn initialize_config(
+    svm: &mut LiteSVM,
+    fee_bps: u16,
+    treasury: &Pubkey,
+    allowed_token_programs: Vec<Pubkey>,
+) -> Keypair {
+    let admin = Keypair::new();
+    svm.airdrop(&admin.pubkey(), 1_000_000_000).unwrap();
+    set_upgrade_authority(svm, Some(admin.pubkey()));
+
+    let ix = instruction_builders::initialize_config(
+        &PROGRAM_ID,
+        &admin.pubkey(),
+        fee_bps,
+        treasury,
+        allowed_token_programs,
+    );
+    send(svm, ix, &admin).expect("InitializeConfig instruction failed");
+    admin
+}
+
+/// Stands in the ProgramData account the upgradeable loader would hold for
+/// the program; LiteSVM loads it without one.
+fn set_upgrade_authority(svm: &mut LiteSVM, upgrade_authority: Option<Pubkey>) {
+    let data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: upgrade_authority,
+    })
+    .unwrap();
+    svm.set_account(
+        get_program_data_address(&PROGRAM_ID),
+        Account {
+            lamports: svm.minimum_balance_for_rent_exemption(data.len()),
+            data,
+            owner: solana_sdk_ids::bpf_loader_upgradeable::ID,
+            executable: false,
+            rent_epoch: 0,
+        },
+    )
+    .unwrap();
+}
+
+fn send(svm: &mut LiteSVM, ix: Instruction, signer: &Keypair) -> Result<(), TransactionError> {
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&signer.pubkey()),
+        &[signer],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).map(|_| ()).map_err(|e| e.err)
+}
+
+fn both_token_programs() -> Vec<Pubkey> {
+    vec![spl_token_interface::ID, spl_token_2022_interface::ID]
+}
+
+#[test]
+fn take_routes_protocol_fee_to_treasury() {
+    let (mut svm, maker, taker, mut take_ix, _refund_ix) = setup_validation();
+    let mint_b = take_ix.accounts[3].pubkey;
+    let maker_token_b = take_ix.accounts[6].pubkey;
+    let taker_token_b = take_ix.accounts[5].pubkey;
+
+    let treasury = Pubkey::new_unique();
+    initialize_config(&mut svm, 200, &treasury, both_token_programs());
+
+    let config = Config::unpack(
+        &svm.get_account(&derive_config_pda(&PROGRAM_ID).0)
+            .unwrap()
+            .data,
+    )
+    .unwrap();
+    assert_eq!(config.treasury, treasury);
+    assert_eq!(config.fee_bps, 200);
+
+    // The builder defaulted the treasury account to the maker's, which the
+    // program must refuse once a fee is due.
+    assert_rejected(
+        &mut svm,
+        take_ix.clone(),
+        &taker,
+        EscrowError::InvalidTreasury,
+    );
+
+    let treasury_token_b = create_token_account(
+        &mut svm,
+        &maker,
+        &treasury,
+        &mint_b,
+        &spl_token_interface::ID,
+    );
+    take_ix.accounts[13].pubkey = treasury_token_b;
+    let tx = Transaction::new_signed_with_payer(
+        &[take_ix],
+        Some(&taker.pubkey()),
+        &[&taker],
+        svm.latest_blockhash(),
+    );
+    let meta = svm.send_transaction(tx).expect("Take instruction failed");
+
+    // 2% of 50 is exactly 1.
+    assert_eq!(token_balance(&svm, &treasury_token_b), 1);
+    assert_eq!(token_balance(&svm, &maker_token_b), 49);
+    assert_eq!(token_balance(&svm, &taker_token_b), 0);
+    assert!(matches!(
+        escrow_events(&meta.logs)[..],
+        [EscrowEvent::Filled {
+            paid: 50,
+            protocol_fee: 1,
+            remaining: 0,
+            ..
+        }]
+    ));
+}
+
+#[test]
+fn take_rejects_wrong_config_account() {
+    let (mut svm, _maker, taker, mut take_ix, _refund_ix) = setup_validation();
+
+    take_ix.accounts[12].pubkey = Pubkey::new_unique();
+    assert_rejected(&mut svm, take_ix, &taker, EscrowError::InvalidConfig);
+}
+
+#[test]
+fn config_fee_is_admin_only_and_capped() {
+    let (mut svm, maker, _taker, _take_ix, _refund_ix) = setup_validation();
+    let admin = initialize_config(&mut svm, 100, &Pubkey::new_unique(), both_token_programs());
+
+    let ix = instruction_builders::initialize_config(
+        &PROGRAM_ID,
+        &admin.pubkey(),
+        0,
+        &admin.pubkey(),
+        both_token_programs(),
+    );
+    assert_rejected(&mut svm, ix, &admin, EscrowError::ConfigAlreadyExists);
+
+    let ix = instruction_builders::update_fee(&PROGRAM_ID, &maker.pubkey(), 0);
+    assert_rejected(&mut svm, ix, &maker, EscrowError::Unauthorized);
+
+    let ix =
+        instruction_builders::update_fee(&PROGRAM_ID, &admin.pubkey(), Config::MAX_FEE_BPS + 1);
+    assert_rejected(&mut svm, ix, &admin, EscrowError::FeeTooHigh);
+
+    let ix = instruction_builders::update_fee(&PROGRAM_ID, &admin.pubkey(), Config::MAX_FEE_BPS);
+    send(&mut svm, ix, &admin).expect("UpdateFee instruction failed");
+
+    let config = Config::unpack(
+        &svm.get_account(&derive_config_pda(&PROGRAM_ID).0)
+            .unwrap()
+            .data,
+    )
+    .unwrap();
+    assert_eq!(config.fee_bps, Config::MAX_FEE_BPS);
+    assert_eq!(config.admin, admin.pubkey());
+}
+
+#[test]
+fn only_upgrade_authority_initializes_config() {
+    let (mut svm, maker, _taker, _take_ix, _refund_ix) = setup_validation();
+    let authority = Keypair::new();
+    svm.airdrop(&authority.pubkey(), 1_000_000_000).unwrap();
+    let init = |signer: &Keypair| {
+        instruction_builders::initialize_config(
+            &PROGRAM_ID,
+            &signer.pubkey(),
+            0,
+            &signer.pubkey(),
+            both_token_programs(),
+        )
+    };
+
+    // No ProgramData account at all.
+    assert_rejected(
+        &mut svm,
+        init(&maker),
+        &maker,
+        EscrowError::InvalidProgramData,
+    );
+
+    // A program whose upgrade authority was revoked has no one to claim it.
+    set_upgrade_authority(&mut svm, None);
+    assert_rejected(
+        &mut svm,
+        init(&maker),
+        &maker,
+        EscrowError::NotUpgradeAuthority,
+    );
+
+    set_upgrade_authority(&mut svm, Some(authority.pubkey()));
+    assert_rejected(
+        &mut svm,
+        init(&maker),
+        &maker,
+        EscrowError::NotUpgradeAuthority,
+    );
+
+    let mut ix = init(&authority);
+    ix.accounts[3].pubkey = Pubkey::new_unique();
+    assert_rejected(&mut svm, ix, &authority, EscrowError::InvalidProgramData);
+
+    send(&mut svm, init(&authority), &authority).expect("InitializeConfig instruction failed");
+    let config = Config::unpack(
+        &svm.get_account(&derive_config_pda(&PROGRAM_ID).0)
+            .unwrap()
+            .data,
+    )
+    .unwrap();
+    assert_eq!(config.admin, authority.pubkey());
+}
+
+#[test]
+fn initialize_config_takes_over_a_prefunded_address() {
+    let (mut svm, _maker, _taker, _take_ix, _refund_ix) = setup_validation();
+    let (config_pda, _) = derive_config_pda(&PROGRAM_ID);
+
+    // A few lamports sent to the config address ahead of time must not
+    // block initialization.
+    svm.airdrop(&config_pda, 1_000).unwrap();
+    let admin = initialize_config(&mut svm, 30, &Pubkey::new_unique(), both_token_programs());
+
+    let account = svm.get_account(&config_pda).unwrap();
+    assert_eq!(account.owner, PROGRAM_ID);
+    assert_eq!(
+        account.lamports,
+        svm.minimum_balance_for_rent_exemption(Config::LEN)
+    );
+    let config = Config::unpack(&account.data).unwrap();
+    assert_eq!(config.admin, admin.pubkey());
+    assert_eq!(config.fee_bps, 30);
+}
+
+#[test]
+fn config_fee_rounds_up() {
+    let config = Config {
+        account_type: AccountType::Config,
+        version: Config::VERSION,
+        admin: Pubkey::new_unique(),
+        treasury: Pubkey::new_unique(),
+        fee_bps: 30,
+        paused: false,
+        bump: 255,
+        allowed_token_programs: both_token_programs(),
+    };
+    assert_eq!(config.fee_for(0), 0);
+    assert_eq!(config.fee_for(1), 1);
+    assert_eq!(config.fee_for(333), 1);
+    assert_eq!(config.fee_for(10_000), 30);
+    assert_eq!(config.fee_for(10_001), 31);
+    assert_eq!(
+        config.fee_for(u64::MAX),
+        (u64::MAX as u128 * 30).div_ceil(10_000) as u64
+    );
+    let free = Config {
+        fee_bps: 0,
+        ..config
+    };
+    assert_eq!(free.fee_for(u64::MAX), 0);
+}
+
+#[test]
+fn auction_price_falls_linearly() {
+    let auction = Auction {
+        start_price: 100,
+        end_price: 40,
+        start_at: 1_000,
+        end_at: 1_600,
+    };
+    assert_eq!(auction.price_at(0), 100);
+    assert_eq!(auction.price_at(1_000), 100);
+    assert_eq!(auction.price_at(1_001), 100);
+    assert_eq!(auction.price_at(1_300), 70);
+    assert_eq!(auction.price_at(1_599), 41);
+    assert_eq!(auction.price_at(1_600), 40);
+    assert_eq!(auction.price_at(i64::MAX), 40);
+
+    let wide = Auction {
+        start_price: u64::MAX,
+        end_price: 1,
+        start_at: i64::MIN,
+        end_at: i64::MAX,
+    };
+    assert_eq!(wide.price_at(i64::MIN), u64::MAX);
+    assert_eq!(wide.price_at(0), u64::MAX / 2 + 1);
+}
+
+#[test]
+fn pause_blocks_make_and_take_but_not_refund() {
+    let (mut svm, maker, taker, take_ix, refund_ix) = setup_validation();
+    let admin = initialize_config(&mut svm, 0, &Pubkey::new_unique(), both_token_programs());
+
+    let ix = instruction_builders::set_paused(&PROGRAM_ID, &maker.pubkey(), true);
+    assert_rejected(&mut svm, ix, &maker, EscrowError::Unauthorized);
+
+    let ix = instruction_builders::set_paused(&PROGRAM_ID, &admin.pubkey(), true);
+    send(&mut svm, ix, &admin).expect("SetPaused instruction failed");
+
+    assert_rejected(
+        &mut svm,
+        take_ix.clone(),
+        &taker,
+        EscrowError::ProgramPaused,
+    );
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &take_ix.accounts[2].pubkey,
+        &take_ix.accounts[3].pubkey,
+        &refund_ix.accounts[3].pubkey,
+        &spl_token_interface::ID,
+        MakeArgs {
+            seed: SEED + 1,
+            amount_offered: 10,
+            amount_required: 5,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    assert_rejected(&mut svm, make_ix, &maker, EscrowError::ProgramPaused);
+
+    let maker_token_a = refund_ix.accounts[3].pubkey;
+    send(&mut svm, refund_ix, &maker).expect("Refund instruction failed");
+    assert_eq!(token_balance(&svm, &maker_token_a), 100);
+}
+
+#[test]
+fn transfer_admin_hands_over_config() {
+    let (mut svm, maker, _taker, _take_ix, _refund_ix) = setup_validation();
+    let admin = initialize_config(&mut svm, 0, &Pubkey::new_unique(), both_token_programs());
+
+    let ix = instruction_builders::transfer_admin(&PROGRAM_ID, &admin.pubkey(), &maker.pubkey());
+    send(&mut svm, ix, &admin).expect("TransferAdmin instruction failed");
+
+    let ix = instruction_builders::set_paused(&PROGRAM_ID, &admin.pubkey(), true);
+    assert_rejected(&mut svm, ix, &admin, EscrowError::Unauthorized);
+
+    let ix = instruction_builders::set_paused(&PROGRAM_ID, &maker.pubkey(), true);
+    send(&mut svm, ix, &maker).expect("SetPaused instruction failed");
+
+    let config = Config::unpack(
+        &svm.get_account(&derive_config_pda(&PROGRAM_ID).0)
+            .unwrap()
+            .data,
+    )
+    .unwrap();
+    assert_eq!(config.admin, maker.pubkey());
+    assert!(config.paused);
+}
+
+#[test]
+fn config_rejects_disallowed_token_program() {
+    let (mut svm, maker, taker, take_ix, _refund_ix) = setup_validation();
+    let admin = initialize_config(
+        &mut svm,
+        0,
+        &Pubkey::new_unique(),
+        vec![spl_token_2022_interface::ID],
+    );
+
+    assert_rejected(
+        &mut svm,
+        take_ix.clone(),
+        &taker,
+        EscrowError::TokenProgramNotAllowed,
+    );
+
+    let ix = instruction_builders::set_allowed_token_programs(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        both_token_programs(),
+    );
+    assert_rejected(&mut svm, ix, &maker, EscrowError::Unauthorized);
+    let ix = instruction_builders::set_allowed_token_programs(&PROGRAM_ID, &admin.pubkey(), vec![]);
+    assert_rejected(&mut svm, ix, &admin, EscrowError::NoTokenPrograms);
+
+    let ix = instruction_builders::set_allowed_token_programs(
+        &PROGRAM_ID,
+        &admin.pubkey(),
+        both_token_programs(),
+    );
+    send(&mut svm, ix, &admin).expect("SetAllowedTokenPrograms instruction failed");
+    send(&mut svm, take_ix, &taker).expect("Take instruction failed");
+}
+
+#[test]
+fn config_rejects_empty_token_program_allowlist() {
+    let (mut svm, _maker, _taker, _take_ix, _refund_ix) = setup_validation();
+    let authority = Keypair::new();
+    svm.airdrop(&authority.pubkey(), 1_000_000_000).unwrap();
+    set_upgrade_authority(&mut svm, Some(authority.pubkey()));
+
+    let ix = instruction_builders::initialize_config(
+        &PROGRAM_ID,
+        &authority.pubkey(),
+        0,
+        &authority.pubkey(),
+        vec![],
+    );
+    assert_rejected(&mut svm, ix, &authority, EscrowError::NoTokenPrograms);
+}
+
+fn update_args() -> UpdateArgs {
+    UpdateArgs {
+        receive_amount: None,
+        deposit: 0,
+        withdraw: 0,
+        expires_at: None,
+    }
+}
+
+#[test]
+fn update_changes_terms() {
+    let (mut svm, maker, taker, take_ix, refund_ix) = setup_validation();
+    let maker_token_a = refund_ix.accounts[3].pubkey;
+    let escrow_pda = refund_ix.accounts[4].pubkey;
+    let vault_pda = refund_ix.accounts[5].pubkey;
+    let update_accounts = UpdateAccounts {
+        maker: maker.pubkey(),
+        mint_a: take_ix.accounts[2].pubkey,
+        mint_b: take_ix.accounts[3].pubkey,
+        maker_token_a,
+        token_program: spl_token_interface::ID,
+    };
+    let update_ix = |args| instruction_builders::update(&PROGRAM_ID, &update_accounts, SEED, args);
+
+    // Withdrawing everything is what refund is for.
+    let ix = update_ix(UpdateArgs {
+        withdraw: 100,
+        ..update_args()
+    });
+    assert_rejected(&mut svm, ix, &maker, EscrowError::InvalidAmount);
+    let ix = update_ix(UpdateArgs {
+        deposit: 1,
+        withdraw: 1,
+        ..update_args()
+    });
+    assert_rejected(&mut svm, ix, &maker, EscrowError::InvalidAmount);
+    let ix = update_ix(UpdateArgs {
+        receive_amount: Some(0),
+        ..update_args()
+    });
+    assert_rejected(&mut svm, ix, &maker, EscrowError::InvalidAmount);
+
+    let mut ix = update_ix(update_args());
+    ix.accounts[0].pubkey = taker.pubkey();
+    assert_rejected(&mut svm, ix, &taker, EscrowError::MakerMismatch);
+
+    let ix = update_ix(UpdateArgs {
+        receive_amount: Some(20),
+        withdraw: 60,
+        expires_at: Some(Some(svm.get_sysvar::<Clock>().unix_timestamp + 3_600)),
+        ..update_args()
+    });
+    let meta = svm
+        .send_transaction(Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&maker.pubkey()),
+            &[&maker],
+            svm.latest_blockhash(),
+        ))
+        .expect("Update instruction failed");
+    assert_eq!(token_balance(&svm, &maker_token_a), 60);
+    assert_eq!(token_balance(&svm, &vault_pda), 40);
+    assert!(matches!(
+        escrow_events(&meta.logs)[..],
+        [EscrowEvent::Updated {
+            amount: 40,
+            receive_amount: 20,
+            expires_at: Some(_),
+            ..
+        }]
+    ));
+
+    let ix = update_ix(UpdateArgs {
+        deposit: 10,
+        expires_at: Some(None),
+        ..update_args()
+    });
+    send(&mut svm, ix, &maker).expect("Update instruction failed");
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 50);
+    assert_eq!(escrow.receive_amount, 20);
+    assert_eq!(escrow.expires_at, None);
+
+    // The new price applies to fills: paying 10 of 20 releases half of 50.
+    let mut take_ix = take_ix;
+    let mut data = vec![1u8]; // discriminator for take fn
+    data.extend_from_slice(&10u64.to_le_bytes());
+    take_ix.data = data;
+    send(&mut svm, take_ix.clone(), &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &take_ix.accounts[4].pubkey), 25);
+}
+
+#[test]
+fn update_rescales_other_prices() {
+    let (mut svm, maker, _taker, take_ix, refund_ix) = setup_validation();
+    let maker_token_a = refund_ix.accounts[3].pubkey;
+    let escrow_pda = refund_ix.accounts[4].pubkey;
+    let update_accounts = UpdateAccounts {
+        maker: maker.pubkey(),
+        mint_a: take_ix.accounts[2].pubkey,
+        mint_b: take_ix.accounts[3].pubkey,
+        maker_token_a,
+        token_program: spl_token_interface::ID,
+    };
+    let update_ix = |args| instruction_builders::update(&PROGRAM_ID, &update_accounts, SEED, args);
+    let mint_c = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+    let ix = instruction_builders::set_payment_mint(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &escrow_pda,
+        &mint_c,
+        80,
+    );
+    send(&mut svm, ix, &maker).expect("SetPaymentMint failed");
+
+    // 80 of mint C bought all 100; it buys the 40 left for 32.
+    let ix = update_ix(UpdateArgs {
+        withdraw: 60,
+        ..update_args()
+    });
+    send(&mut svm, ix, &maker).expect("Update instruction failed");
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 40);
+    assert_eq!(escrow.receive_amount, 50);
+    assert_eq!(escrow.payment_options[0].receive_amount, 32);
+
+    let now = svm.get_sysvar::<Clock>().unix_timestamp;
+    let ix = instruction_builders::set_auction(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &escrow_pda,
+        Some(Auction {
+            start_price: 40,
+            end_price: 20,
+            start_at: now,
+            end_at: now + 3_600,
+        }),
+    );
+    send(&mut svm, ix, &maker).expect("SetAuction failed");
+
+    // Topping up to 50 raises every price by a quarter.
+    let ix = update_ix(UpdateArgs {
+        deposit: 10,
+        ..update_args()
+    });
+    send(&mut svm, ix, &maker).expect("Update instruction failed");
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 50);
+    assert_eq!(escrow.payment_options[0].receive_amount, 40);
+    let auction = escrow.auction.unwrap();
+    assert_eq!((auction.start_price, auction.end_price), (50, 25));
+}
+
+#[test]
+fn sponsored_rent_returns_to_rent_payer() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        _vault,
+    ) = setup_escrow();
+    let relayer = Keypair::new();
+    svm.airdrop(&relayer.pubkey(), 1_000_000_000).unwrap();
+
+    let make_escrow = |svm: &mut LiteSVM, seed: u64| {
+        let ix = instruction_builders::sponsor_rent(
+            instruction_builders::make(
+                &PROGRAM_ID,
+                &maker.pubkey(),
+                &mint_a,
+                &mint_b,
+                &maker_token_a,
+                &spl_token_interface::ID,
+                MakeArgs {
+                    seed,
+                    amount_offered: 50,
+                    amount_required: 25,
+                    expires_at: None,
+                    allowed_taker: None,
+                    receive_fee_mode: FeeMode::Gross,
+                    basket: Vec::new(),
+                    kind: EscrowKind::Swap,
+                },
+            ),
+            &relayer.pubkey(),
+        );
+        let tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&maker.pubkey()),
+            &[&maker, &relayer],
+            svm.latest_blockhash(),
+        );
+        svm.send_transaction(tx).expect("Make instruction failed");
+    };
+
+    let relayer_start = svm.get_balance(&relayer.pubkey()).unwrap();
+    let maker_start = svm.get_balance(&maker.pubkey()).unwrap();
+    make_escrow(&mut svm, SEED);
+
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.rent_payer, relayer.pubkey());
+    assert!(svm.get_balance(&relayer.pubkey()).unwrap() < relayer_start);
+    // The maker only paid the transaction fee.
+    assert_eq!(
+        maker_start - svm.get_balance(&maker.pubkey()).unwrap(),
+        5_000
+    );
+
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &spl_token_interface::ID,
+        SEED,
+    );
+    assert_rejected(
+        &mut svm,
+        refund_ix.clone(),
+        &maker,
+        EscrowError::InvalidRentPayer,
+    );
+    let ix = instruction_builders::return_rent_to(refund_ix, &relayer.pubkey());
+    send(&mut svm, ix, &maker).expect("Refund instruction failed");
+    assert_eq!(svm.get_balance(&relayer.pubkey()).unwrap(), relayer_start);
+
+    make_escrow(&mut svm, SEED + 1);
+    // Growing a sponsored escrow for another payment mint is paid by the
+    // relayer, which gets it back with the rest of the rent.
+    let (escrow_pda, _) =
+        derive_escrow_pda(&PROGRAM_ID, &maker.pubkey(), &mint_a, &mint_b, SEED + 1);
+    let mint_c = create_mint(&mut svm, 6, &maker, &spl_token_interface::ID);
+    let set_payment_mint = instruction_builders::set_payment_mint(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &escrow_pda,
+        &mint_c,
+        40,
+    );
+    assert_rejected(
+        &mut svm,
+        set_payment_mint.clone(),
+        &maker,
+        EscrowError::InvalidRentPayer,
+    );
+    let ix = instruction_builders::sponsor_rent(set_payment_mint, &relayer.pubkey());
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&maker.pubkey()),
+        &[&maker, &relayer],
+        svm.latest_blockhash(),
+    );
+    svm.send_transaction(tx).expect("SetPaymentMint failed");
+    let maker_before_take = svm.get_balance(&maker.pubkey()).unwrap();
+
+    let take_ix = instruction_builders::return_rent_to(
+        instruction_builders::take(
+            &PROGRAM_ID,
+            &TakeAccounts {
+                taker: taker.pubkey(),
+                maker: maker.pubkey(),
+                mint_a,
+                mint_b,
+                taker_token_a,
+                taker_token_b,
+                maker_token_b,
+                treasury_token_b: maker_token_b,
+                token_program_a: spl_token_interface::ID,
+                token_program_b: spl_token_interface::ID,
+            },
+            SEED + 1,
+            25,
+        ),
+        &relayer.pubkey(),
+    );
+    send(&mut svm, take_ix, &taker).expect("Take instruction failed");
+    assert_eq!(svm.get_balance(&relayer.pubkey()).unwrap(), relayer_start);
+    assert_eq!(svm.get_balance(&maker.pubkey()).unwrap(), maker_before_take);
+}
+
+#[test]
+fn take_creates_missing_token_accounts() {
+    let mut svm = LiteSVM::new();
+    svm.add_program_from_file(PROGRAM_ID, "../target/deploy/escrow.so")
+        .unwrap();
+
+    let maker = Keypair::new();
+    let taker = Keypair::new();
+    svm.airdrop(&maker.pubkey(), 100_000_000_000).unwrap();
+    svm.airdrop(&taker.pubkey(), 100_000_000_000).unwrap();
+
+    let token_program = spl_token_interface::ID;
+    let mint_a = create_mint(&mut svm, 6, &maker, &token_program);
+    let mint_b = create_mint(&mut svm, 6, &maker, &token_program);
+
+    // Only the accounts being paid from exist up front.
+    let maker_token_a =
+        create_token_account(&mut svm, &maker, &maker.pubkey(), &mint_a, &token_program);
+    let taker_token_b =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_b, &token_program);
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        100,
+        &token_program,
+    );
+    mint_tokens(
+        &mut svm,
+        &taker,
+        &mint_b,
+        &maker,
+        &taker_token_b,
+        50,
+        &token_program,
+    );
+
+    let taker_token_a =
+        get_associated_token_address_with_program_id(&taker.pubkey(), &mint_a, &token_program);
+    let maker_token_b =
+        get_associated_token_address_with_program_id(&maker.pubkey(), &mint_b, &token_program);
+    assert!(svm.get_account(&taker_token_a).is_none());
+    assert!(svm.get_account(&maker_token_b).is_none());
+
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    send(&mut svm, make_ix, &maker).expect("Make instruction failed");
+
+    let take_ix = instruction_builders::take(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker: taker.pubkey(),
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            taker_token_a,
+            taker_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            token_program_a: token_program,
+            token_program_b: token_program,
+        },
+        SEED,
+        20,
+    );
+    let taker_lamports = svm.get_balance(&taker.pubkey()).unwrap();
+    let maker_lamports = svm.get_balance(&maker.pubkey()).unwrap();
+    send(&mut svm, take_ix.clone(), &taker).expect("Take instruction failed");
+
+    assert_eq!(token_balance(&svm, &taker_token_a), 40);
+    assert_eq!(token_balance(&svm, &maker_token_b), 20);
+    // The taker funded both accounts; the maker paid nothing.
+    assert_eq!(svm.get_balance(&maker.pubkey()).unwrap(), maker_lamports);
+    let rent = svm.minimum_balance_for_rent_exemption(TokenAccount::LEN);
+    assert_eq!(
+        taker_lamports - svm.get_balance(&taker.pubkey()).unwrap(),
+        2 * rent + 5_000
+    );
+
+    // Existing accounts are reused on the next fill.
+    let mut take_ix = take_ix;
+    let mut data = vec![1u8]; // discriminator for take fn
+    data.extend_from_slice(&30u64.to_le_bytes());
+    take_ix.data = data;
+    send(&mut svm, take_ix, &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 100);
+    assert_eq!(token_balance(&svm, &maker_token_b), 50);
+}
+
+#[test]
+fn take_rejects_wrong_associated_token_program() {
+    let (mut svm, _maker, taker, mut take_ix, _refund_ix) = setup_validation();
+
+    take_ix.accounts[14].pubkey = spl_token_interface::ID;
+    assert_rejected(
+        &mut svm,
+        take_ix,
+        &taker,
+        EscrowError::InvalidAssociatedTokenProgram,
+    );
+}
+
+#[test]
+fn basket_is_released_whole() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        _vault,
+    ) = setup_escrow();
+    let token_program = spl_token_interface::ID;
+
+    let basket_mints: Vec<Pubkey> = (0..2)
+        .map(|_| create_mint(&mut svm, 6, &maker, &token_program))
+        .collect();
+    let maker_basket: Vec<Pubkey> = basket_mints
+        .iter()
+        .map(|mint| {
+            let account =
+                create_token_account(&mut svm, &maker, &maker.pubkey(), mint, &token_program);
+            mint_tokens(&mut svm, &maker, mint, &maker, &account, 60, &token_program);
+            account
+        })
+        .collect();
+    let legs = |owner: &Pubkey, token_accounts: Option<&[Pubkey]>| -> Vec<BasketLegAccounts> {
+        basket_mints
+            .iter()
+            .enumerate()
+            .map(|(i, mint)| BasketLegAccounts {
+                mint: *mint,
+                token_account: token_accounts.map_or_else(
+                    || get_associated_token_address_with_program_id(owner, mint, &token_program),
+                    |accounts| accounts[i],
+                ),
+                token_program,
+            })
+            .collect()
+    };
+
+    let make_basket = |seed: u64, legs: &[BasketLegAccounts]| {
+        let (escrow, _) = instruction_builders::find_escrow_address(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &mint_b,
+            seed,
+        );
+        instruction_builders::with_basket(
+            &PROGRAM_ID,
+            instruction_builders::make(
+                &PROGRAM_ID,
+                &maker.pubkey(),
+                &mint_a,
+                &mint_b,
+                &maker_token_a,
+                &token_program,
+                MakeArgs {
+                    seed,
+                    amount_offered: 50,
+                    amount_required: 50,
+                    expires_at: None,
+                    allowed_taker: None,
+                    receive_fee_mode: FeeMode::Gross,
+                    basket: vec![30; legs.len()],
+                    kind: EscrowKind::Swap,
+                },
+            ),
+            &escrow,
+            legs,
+        )
+    };
+    let maker_legs = legs(&maker.pubkey(), Some(&maker_basket));
+
+    send(&mut svm, make_basket(SEED, &maker_legs), &maker).expect("Make instruction failed");
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.basket.len(), 2);
+    for (leg, mint) in escrow.basket.iter().zip(&basket_mints) {
+        assert_eq!(leg.mint, *mint);
+        assert_eq!(leg.amount, 30);
+        let (vault, _) =
+            instruction_builders::find_basket_vault_address(&PROGRAM_ID, &escrow_pda, mint);
+        assert_eq!(token_balance(&svm, &vault), 30);
+    }
+
+    // The same mint cannot be bundled twice, nor can mint A or mint B.
+    for (mint, token_account) in [(basket_mints[0], maker_basket[0]), (mint_a, maker_token_a)] {
+        let legs = [
+            BasketLegAccounts {
+                mint: basket_mints[0],
+                token_account: maker_basket[0],
+                token_program,
+            },
+            BasketLegAccounts {
+                mint,
+                token_account,
+                token_program,
+            },
+        ];
+        assert_rejected(
+            &mut svm,
+            make_basket(SEED + 2, &legs),
+            &maker,
+            EscrowError::InvalidBasketMint,
+        );
+    }
+
+    let take = |seed: u64, amount: u64| {
+        let (escrow, _) = instruction_builders::find_escrow_address(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &mint_b,
+            seed,
+        );
+        instruction_builders::with_basket(
+            &PROGRAM_ID,
+            instruction_builders::take(
+                &PROGRAM_ID,
+                &TakeAccounts {
+                    taker: taker.pubkey(),
+                    maker: maker.pubkey(),
+                    mint_a,
+                    mint_b,
+                    taker_token_a,
+                    taker_token_b,
+                    maker_token_b,
+                    treasury_token_b: maker_token_b,
+                    token_program_a: token_program,
+                    token_program_b: token_program,
+                },
+                seed,
+                amount,
+            ),
+            &escrow,
+            &legs(&taker.pubkey(), None),
+        )
+    };
+
+    assert_rejected(
+        &mut svm,
+        take(SEED, 20),
+        &taker,
+        EscrowError::PartialFillNotAllowed,
+    );
+    send(&mut svm, take(SEED, 50), &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 50);
+    for leg in legs(&taker.pubkey(), None) {
+        assert_eq!(token_balance(&svm, &leg.token_account), 30);
+        let (vault, _) =
+            instruction_builders::find_basket_vault_address(&PROGRAM_ID, &escrow_pda, &leg.mint);
+        assert!(svm
+            .get_account(&vault)
+            .is_none_or(|vault| vault.lamports == 0));
+    }
+
+    // A refund hands every leg back to the maker.
+    send(&mut svm, make_basket(SEED + 1, &maker_legs), &maker).expect("Make instruction failed");
+    let (escrow, _) = instruction_builders::find_escrow_address(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        SEED + 1,
+    );
+    let refund_ix = instruction_builders::with_basket(
+        &PROGRAM_ID,
+        instruction_builders::refund(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &mint_b,
+            &maker_token_a,
+            &token_program,
+            SEED + 1,
+        ),
+        &escrow,
+        &legs(&maker.pubkey(), Some(&maker_basket)),
+    );
+    send(&mut svm, refund_ix, &maker).expect("Refund instruction failed");
+    for account in &maker_basket {
+        assert_eq!(token_balance(&svm, account), 30);
+    }
+    assert!(svm
+        .get_account(&escrow)
+        .is_none_or(|escrow| escrow.lamports == 0));
+}
+
+#[test]
+fn take_settles_in_any_payment_mint() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        _vault,
+    ) = setup_escrow();
+    let token_program = spl_token_interface::ID;
+    let mint_c = create_mint(&mut svm, 6, &maker, &token_program);
+    let taker_token_c =
+        create_token_account(&mut svm, &taker, &taker.pubkey(), &mint_c, &token_program);
+    mint_tokens(
+        &mut svm,
+        &taker,
+        &mint_c,
+        &maker,
+        &taker_token_c,
+        80,
+        &token_program,
+    );
+    let maker_token_c =
+        get_associated_token_address_with_program_id(&maker.pubkey(), &mint_c, &token_program);
+
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    send(&mut svm, make_ix, &maker).expect("Make instruction failed");
+
+    let take_accounts = TakeAccounts {
+        taker: taker.pubkey(),
+        maker: maker.pubkey(),
+        mint_a,
+        mint_b,
+        taker_token_a,
+        taker_token_b: taker_token_c,
+        maker_token_b: maker_token_c,
+        treasury_token_b: maker_token_c,
+        token_program_a: token_program,
+        token_program_b: token_program,
+    };
+    let take_in_c = |amount| {
+        instruction_builders::take_paying_with(&PROGRAM_ID, &take_accounts, &mint_c, SEED, amount)
+    };
+    assert_rejected(&mut svm, take_in_c(20), &taker, EscrowError::MintMismatch);
+
+    let set_payment_mint = |mint: &Pubkey, receive_amount| {
+        instruction_builders::set_payment_mint(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &escrow_pda,
+            mint,
+            receive_amount,
+        )
+    };
+    assert_rejected(
+        &mut svm,
+        set_payment_mint(&mint_b, 40),
+        &maker,
+        EscrowError::InvalidPaymentMint,
+    );
+    assert_rejected(
+        &mut svm,
+        instruction_builders::set_payment_mint(
+            &PROGRAM_ID,
+            &taker.pubkey(),
+            &escrow_pda,
+            &mint_c,
+            80,
+        ),
+        &taker,
+        EscrowError::MakerMismatch,
+    );
+    // The escrow is made without room for options and grows to fit one, with
+    // the maker paying the rent.
+    let len_before = svm.get_account(&escrow_pda).unwrap().data.len();
+    send(&mut svm, set_payment_mint(&mint_c, 80), &maker).expect("SetPaymentMint failed");
+    let account = svm.get_account(&escrow_pda).unwrap();
+    assert_eq!(account.data.len(), len_before + PaymentOption::LEN);
+    assert_eq!(
+        account.lamports,
+        svm.minimum_balance_for_rent_exemption(account.data.len())
+    );
+
+    // A quarter of the price in mint C releases a quarter of the deposit, and
+    // mint B's price shrinks with it, rounded up for the maker.
+    send(&mut svm, take_in_c(20), &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 25);
+    assert_eq!(token_balance(&svm, &maker_token_c), 20);
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 75);
+    assert_eq!(escrow.receive_amount, 38);
+    assert_eq!(
+        escrow.payment_options,
+        vec![PaymentOption {
+            mint: mint_c,
+            receive_amount: 60,
+        }]
+    );
+
+    // Dropping the option keeps the space for the next one.
+    send(&mut svm, set_payment_mint(&mint_c, 0), &maker).expect("SetPaymentMint failed");
+    assert_eq!(
+        svm.get_account(&escrow_pda).unwrap().data.len(),
+        len_before + PaymentOption::LEN
+    );
+    assert_rejected(&mut svm, take_in_c(60), &taker, EscrowError::MintMismatch);
+
+    let take_ix = instruction_builders::take(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            ..take_accounts
+        },
+        SEED,
+        38,
+    );
+    send(&mut svm, take_ix, &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 100);
+    assert_eq!(token_balance(&svm, &maker_token_b), 38);
+}
+
+#[test]
+fn take_pays_the_current_auction_price() {
+    let (
+        mut svm,
+        maker,
+        taker,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        taker_token_a,
+        taker_token_b,
+        (escrow_pda, _escrow_bump),
+        _vault,
+    ) = setup_escrow();
+    let token_program = spl_token_interface::ID;
+    let set_time = |svm: &mut LiteSVM, unix_timestamp| {
+        let mut clock = svm.get_sysvar::<Clock>();
+        clock.unix_timestamp = unix_timestamp;
+        svm.set_sysvar(&clock);
+    };
+    set_time(&mut svm, 1_000);
+
+    let make_ix = instruction_builders::make(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &mint_b,
+        &maker_token_a,
+        &token_program,
+        MakeArgs {
+            seed: SEED,
+            amount_offered: 100,
+            amount_required: 50,
+            expires_at: None,
+            allowed_taker: None,
+            receive_fee_mode: FeeMode::Gross,
+            basket: Vec::new(),
+            kind: EscrowKind::Swap,
+        },
+    );
+    send(&mut svm, make_ix, &maker).expect("Make instruction failed");
+
+    let auction = Auction {
+        start_price: 80,
+        end_price: 40,
+        start_at: 1_000,
+        end_at: 2_000,
+    };
+    let set_auction = |auction| {
+        instruction_builders::set_auction(&PROGRAM_ID, &maker.pubkey(), &escrow_pda, auction)
+    };
+    for invalid in [
+        Auction {
+            end_price: 80,
+            ..auction
+        },
+        Auction {
+            end_price: 0,
+            ..auction
+        },
+        Auction {
+            end_at: 1_000,
+            ..auction
+        },
+        Auction {
+            start_at: 0,
+            end_at: 500,
+            ..auction
+        },
+    ] {
+        assert_rejected(
+            &mut svm,
+            set_auction(Some(invalid)),
+            &maker,
+            EscrowError::InvalidAuction,
+        );
+    }
+    assert_rejected(
+        &mut svm,
+        instruction_builders::set_auction(&PROGRAM_ID, &taker.pubkey(), &escrow_pda, None),
+        &taker,
+        EscrowError::MakerMismatch,
+    );
+    send(&mut svm, set_auction(Some(auction)), &maker).expect("SetAuction failed");
+
+    // A fixed price would be ignored while the auction runs.
+    let update_ix = instruction_builders::update(
+        &PROGRAM_ID,
+        &UpdateAccounts {
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b,
+            maker_token_a,
+            token_program,
+        },
+        SEED,
+        UpdateArgs {
+            receive_amount: Some(70),
+            ..update_args()
+        },
+    );
+    assert_rejected(&mut svm, update_ix, &maker, EscrowError::AuctionActive);
+
+    let take = |amount| {
+        instruction_builders::take(
+            &PROGRAM_ID,
+            &TakeAccounts {
+                taker: taker.pubkey(),
+                maker: maker.pubkey(),
+                mint_a,
+                mint_b,
+                taker_token_a,
+                taker_token_b,
+                maker_token_b,
+                treasury_token_b: maker_token_b,
+                token_program_a: token_program,
+                token_program_b: token_program,
+            },
+            SEED,
+            amount,
+        )
+    };
+
+    // Halfway through, the whole deposit costs 60: paying 30 buys half of it,
+    // and the auction is re-priced for the half left.
+    set_time(&mut svm, 1_500);
+    send(&mut svm, take(30), &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 50);
+    assert_eq!(token_balance(&svm, &maker_token_b), 30);
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 50);
+    assert_eq!(
+        escrow.auction,
+        Some(Auction {
+            start_price: 40,
+            end_price: 20,
+            ..auction
+        })
+    );
+
+    // After the end the floor price holds, and a higher cap only pays it.
+    set_time(&mut svm, 3_000);
+    send(&mut svm, take(1_000), &taker).expect("Take instruction failed");
+    assert_eq!(token_balance(&svm, &taker_token_a), 100);
+    assert_eq!(token_balance(&svm, &maker_token_b), 50);
+    assert_eq!(svm.get_balance(&escrow_pda).unwrap_or(0), 0);
+}
+
+#[test]
+fn arbitrated_escrow_is_settled_by_ruling() {
+    let (
+        mut svm,
+        maker,
+        seller,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        seller_token_a,
+        seller_token_b,
+        _escrow,
+        _vault,
+    ) = setup_escrow();
+    let token_program = spl_token_interface::ID;
+    let arbiter = Keypair::new();
+    let stranger = Keypair::new();
+    svm.airdrop(&arbiter.pubkey(), 1_000_000_000).unwrap();
+    svm.airdrop(&stranger.pubkey(), 1_000_000_000).unwrap();
+    mint_tokens(
+        &mut svm,
+        &maker,
+        &mint_a,
+        &maker,
+        &maker_token_a,
+        200,
+        &token_program,
+    );
+
+    // Arbitrated payments have no mint B.
+    let no_mint_b = Pubkey::default();
+    let make = |seed, mint_b: &Pubkey, amount_required, arbiter: &Pubkey, seller: &Pubkey| {
+        instruction_builders::make(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            mint_b,
+            &maker_token_a,
+            &token_program,
+            MakeArgs {
+                seed,
+                amount_offered: 100,
+                amount_required,
+                expires_at: None,
+                allowed_taker: None,
+                receive_fee_mode: FeeMode::Gross,
+                basket: Vec::new(),
+                kind: EscrowKind::Arbitrated {
+                    arbiter: *arbiter,
+                    seller: *seller,
+                },
+            },
+        )
+    };
+    let make_arbitrated = |svm: &mut LiteSVM, seed| {
+        let make_ix = make(seed, &no_mint_b, 0, &arbiter.pubkey(), &seller.pubkey());
+        send(svm, make_ix, &maker).expect("Make instruction failed");
+    };
+    let settle = |signer: &Keypair, seed, seller_amount| {
+        instruction_builders::settle(
+            &PROGRAM_ID,
+            &SettleAccounts {
+                signer: signer.pubkey(),
+                maker: maker.pubkey(),
+                mint_a,
+                seller_token_a,
+                maker_token_a,
+                token_program,
+            },
+            seed,
+            seller_amount,
+        )
+    };
+
+    for (bad_arbiter, bad_seller) in [
+        (maker.pubkey(), seller.pubkey()),
+        (arbiter.pubkey(), maker.pubkey()),
+        (seller.pubkey(), seller.pubkey()),
+    ] {
+        assert_rejected(
+            &mut svm,
+            make(SEED, &no_mint_b, 0, &bad_arbiter, &bad_seller),
+            &maker,
+            EscrowError::InvalidArbitration,
+        );
+    }
+    // Nor may a payment carry swap terms.
+    for (mint_b, amount_required) in [(mint_b, 0), (no_mint_b, 50)] {
+        assert_rejected(
+            &mut svm,
+            make(
+                SEED,
+                &mint_b,
+                amount_required,
+                &arbiter.pubkey(),
+                &seller.pubkey(),
+            ),
+            &maker,
+            EscrowError::InvalidArbitration,
+        );
+    }
+    for (expires_at, allowed_taker, receive_fee_mode) in [
+        (Some(i64::MAX), None, FeeMode::Gross),
+        (None, Some(stranger.pubkey()), FeeMode::Gross),
+        (None, None, FeeMode::Net),
+    ] {
+        let make_ix = instruction_builders::make(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &no_mint_b,
+            &maker_token_a,
+            &token_program,
+            MakeArgs {
+                seed: SEED,
+                amount_offered: 100,
+                amount_required: 0,
+                expires_at,
+                allowed_taker,
+                receive_fee_mode,
+                basket: Vec::new(),
+                kind: EscrowKind::Arbitrated {
+                    arbiter: arbiter.pubkey(),
+                    seller: seller.pubkey(),
+                },
+            },
+        );
+        assert_rejected(&mut svm, make_ix, &maker, EscrowError::InvalidArbitration);
+    }
+    make_arbitrated(&mut svm, SEED);
+    let (escrow_pda, _) = instruction_builders::find_escrow_address(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &no_mint_b,
+        SEED,
+    );
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(
+        escrow.arbitration,
+        Some(Arbitration {
+            arbiter: arbiter.pubkey(),
+            seller: seller.pubkey(),
+        })
+    );
+
+    // The deposit can never be taken or refunded as a swap, whatever mint the
+    // taker offers.
+    let take_ix = instruction_builders::take_paying_with(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker: seller.pubkey(),
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b: no_mint_b,
+            taker_token_a: seller_token_a,
+            taker_token_b: seller_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            token_program_a: token_program,
+            token_program_b: token_program,
+        },
+        &mint_b,
+        SEED,
+        50,
+    );
+    assert_rejected(&mut svm, take_ix, &seller, EscrowError::Arbitrated);
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &no_mint_b,
+        &maker_token_a,
+        &token_program,
+        SEED,
+    );
+    assert_rejected(&mut svm, refund_ix, &maker, EscrowError::Arbitrated);
+
+    // Each party may only concede; only the arbiter can split.
+    for (signer, seller_amount) in [(&maker, 40), (&seller, 100), (&seller, 40), (&stranger, 0)] {
+        assert_rejected(
+            &mut svm,
+            settle(signer, SEED, seller_amount),
+            signer,
+            EscrowError::InvalidRuling,
+        );
+    }
+    assert_rejected(
+        &mut svm,
+        settle(&arbiter, SEED, 101),
+        &arbiter,
+        EscrowError::AmountExceedsRemaining,
+    );
+    send(&mut svm, settle(&arbiter, SEED, 60), &arbiter).expect("Settle instruction failed");
+    assert_eq!(token_balance(&svm, &seller_token_a), 60);
+    assert_eq!(token_balance(&svm, &maker_token_a), 240);
+    assert_eq!(svm.get_balance(&escrow_pda).unwrap_or(0), 0);
+
+    make_arbitrated(&mut svm, SEED + 1);
+    send(&mut svm, settle(&maker, SEED + 1, 100), &maker).expect("Settle instruction failed");
+    assert_eq!(token_balance(&svm, &seller_token_a), 160);
+    assert_eq!(token_balance(&svm, &maker_token_a), 140);
+
+    make_arbitrated(&mut svm, SEED + 2);
+    send(&mut svm, settle(&seller, SEED + 2, 0), &seller).expect("Settle instruction failed");
+    assert_eq!(token_balance(&svm, &seller_token_a), 160);
+    assert_eq!(token_balance(&svm, &maker_token_a), 140);
+}
+
+#[test]
+fn milestones_are_released_one_by_one() {
+    let (
+        mut svm,
+        maker,
+        beneficiary,
+        mint_a,
+        mint_b,
+        maker_token_a,
+        maker_token_b,
+        beneficiary_token_a,
+        beneficiary_token_b,
+        _escrow,
+        _vault,
+    ) = setup_escrow();
+    let token_program = spl_token_interface::ID;
+
+    // Milestone payments have no mint B.
+    let no_mint_b = Pubkey::default();
+    let milestone = |name: &str, amount| MilestoneArgs {
+        name: name.to_string(),
+        amount,
+    };
+    let make = |mint_b: &Pubkey, amount_required, milestones| {
+        instruction_builders::make(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            mint_b,
+            &maker_token_a,
+            &token_program,
+            MakeArgs {
+                seed: SEED,
+                amount_offered: 100,
+                amount_required,
+                expires_at: None,
+                allowed_taker: None,
+                receive_fee_mode: FeeMode::Gross,
+                basket: Vec::new(),
+                kind: EscrowKind::Milestones {
+                    beneficiary: beneficiary.pubkey(),
+                    milestones,
+                },
+            },
+        )
+    };
+    for (milestones, error) in [
+        (vec![], EscrowError::InvalidMilestones),
+        (
+            vec![milestone("design", 30), milestone("build", 60)],
+            EscrowError::InvalidMilestones,
+        ),
+        (
+            vec![milestone("", 30), milestone("build", 70)],
+            EscrowError::InvalidMilestones,
+        ),
+        (
+            vec![milestone("design", 0), milestone("build", 100)],
+            EscrowError::InvalidMilestones,
+        ),
+        (
+            vec![milestone(&"x".repeat(Milestone::MAX_NAME_LEN + 1), 100)],
+            EscrowError::InvalidMilestones,
+        ),
+        (
+            vec![milestone("step", 10); Escrow::MAX_MILESTONES + 2],
+            EscrowError::TooManyMilestones,
+        ),
+    ] {
+        assert_rejected(&mut svm, make(&no_mint_b, 0, milestones), &maker, error);
+    }
+    let milestones = vec![
+        milestone("design", 30),
+        milestone("build", 50),
+        milestone("launch", 20),
+    ];
+    // Nor may a payment carry swap terms.
+    for (mint_b, amount_required) in [(mint_b, 0), (no_mint_b, 50)] {
+        assert_rejected(
+            &mut svm,
+            make(&mint_b, amount_required, milestones.clone()),
+            &maker,
+            EscrowError::InvalidMilestones,
+        );
+    }
+    for (expires_at, allowed_taker, receive_fee_mode) in [
+        (Some(i64::MAX), None, FeeMode::Gross),
+        (None, Some(beneficiary.pubkey()), FeeMode::Gross),
+        (None, None, FeeMode::Net),
+    ] {
+        let make_ix = instruction_builders::make(
+            &PROGRAM_ID,
+            &maker.pubkey(),
+            &mint_a,
+            &no_mint_b,
+            &maker_token_a,
+            &token_program,
+            MakeArgs {
+                seed: SEED,
+                amount_offered: 100,
+                amount_required: 0,
+                expires_at,
+                allowed_taker,
+                receive_fee_mode,
+                basket: Vec::new(),
+                kind: EscrowKind::Milestones {
+                    beneficiary: beneficiary.pubkey(),
+                    milestones: milestones.clone(),
+                },
+            },
+        );
+        assert_rejected(&mut svm, make_ix, &maker, EscrowError::InvalidMilestones);
+    }
+    send(&mut svm, make(&no_mint_b, 0, milestones), &maker).expect("Make instruction failed");
+    let (escrow_pda, _) = instruction_builders::find_escrow_address(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &no_mint_b,
+        SEED,
+    );
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.beneficiary, Some(beneficiary.pubkey()));
+    assert_eq!(escrow.milestones.len(), 3);
+
+    // Not even by paying in some mint B.
+    let take_ix = instruction_builders::take_paying_with(
+        &PROGRAM_ID,
+        &TakeAccounts {
+            taker: beneficiary.pubkey(),
+            maker: maker.pubkey(),
+            mint_a,
+            mint_b: no_mint_b,
+            taker_token_a: beneficiary_token_a,
+            taker_token_b: beneficiary_token_b,
+            maker_token_b,
+            treasury_token_b: maker_token_b,
+            token_program_a: token_program,
+            token_program_b: token_program,
+        },
+        &mint_b,
+        SEED,
+        50,
+    );
+    assert_rejected(&mut svm, take_ix, &beneficiary, EscrowError::HasMilestones);
+
+    let approve = |index| {
+        instruction_builders::approve_milestone(
+            &PROGRAM_ID,
+            &ApproveMilestoneAccounts {
+                maker: maker.pubkey(),
+                mint_a,
+                beneficiary_token_a,
+                token_program,
+            },
+            SEED,
+            index,
+        )
+    };
+    // Only the funder approves.
+    let mut approve_by_beneficiary = approve(1);
+    approve_by_beneficiary.accounts[0] = AccountMeta::new(beneficiary.pubkey(), true);
+    assert_rejected(
+        &mut svm,
+        approve_by_beneficiary,
+        &beneficiary,
+        EscrowError::MakerMismatch,
+    );
+
+    send(&mut svm, approve(1), &maker).expect("ApproveMilestone instruction failed");
+    assert_eq!(token_balance(&svm, &beneficiary_token_a), 50);
+    let escrow = Escrow::unpack(&svm.get_account(&escrow_pda).unwrap().data).unwrap();
+    assert_eq!(escrow.amount, 50);
+    assert!(escrow.milestones[1].released);
+    assert_rejected(
+        &mut svm,
+        approve(1),
+        &maker,
+        EscrowError::MilestoneUnavailable,
+    );
+    assert_rejected(
+        &mut svm,
+        approve(3),
+        &maker,
+        EscrowError::MilestoneUnavailable,
+    );
+
+    // The milestones still unreleased go back to the funder.
+    let refund_ix = instruction_builders::refund(
+        &PROGRAM_ID,
+        &maker.pubkey(),
+        &mint_a,
+        &no_mint_b,
+        &maker_token_a,
+        &token_program,
+        SEED,
+    );
+    send(&mut svm, refund_ix, &maker).expect("Refund instruction failed");
+    assert_eq!(token_balance(&svm, &maker_token_a), 50);
+    assert_eq!(token_balance(&svm, &beneficiary_token_a), 50);
+    assert_eq!(svm.get_balance(&escrow_pda).unwrap_or(0), 0);
+}